@@ -0,0 +1,311 @@
+use crate::Size;
+
+#[derive(Debug)]
+pub struct BusError;
+
+// Address space of an access, as signaled on the FC0-FC2 pins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FunctionCode {
+    UserData = 1,
+    UserProgram = 2,
+    SupervisorData = 5,
+    SupervisorProgram = 6,
+}
+
+pub trait Bus {
+    fn read(
+        &mut self,
+        addr: u32,
+        s: Size,
+        fc: FunctionCode,
+    ) -> Result<u32, BusError>;
+    fn write(
+        &mut self,
+        addr: u32,
+        s: Size,
+        x: u32,
+        fc: FunctionCode,
+    ) -> Result<(), BusError>;
+
+    // Asserted around the read-modify-write cycles of TAS and CAS, so that
+    // the host may keep other bus masters out.
+    fn set_lock(&mut self, _locked: bool) {}
+
+    // The RESET instruction pulsing the reset line of the devices.
+    fn reset_devices(&mut self) {}
+
+    // Wait states added to the bus cycle starting at `cycle`, e.g. while
+    // DRAM is being refreshed.
+    fn refresh_penalty(&mut self, _cycle: u64) -> u32 {
+        0
+    }
+}
+
+// Debugger accesses, made a byte at a time so that any address is fine and
+// nothing reaches the CPU. Supervisor data space is used.
+pub fn peek(bus: &mut impl Bus, addr: u32, s: Size) -> Result<u32, BusError> {
+    let fc = FunctionCode::SupervisorData;
+    let mut x = 0;
+    for i in 0..s.value() as u32 {
+        x = x << 8 | bus.read(addr.wrapping_add(i), Size::Byte, fc)? & 0xff;
+    }
+    Ok(x)
+}
+
+pub fn poke(
+    bus: &mut impl Bus,
+    addr: u32,
+    s: Size,
+    x: u32,
+) -> Result<(), BusError> {
+    let fc = FunctionCode::SupervisorData;
+    let n = s.value() as u32;
+    for i in 0..n {
+        let byte = x >> (8 * (n - 1 - i)) & 0xff;
+        bus.write(addr.wrapping_add(i), Size::Byte, byte, fc)?;
+    }
+    Ok(())
+}
+
+// Width of the external data bus. The 68008 only has 8 data lines, so every
+// word or long is split into byte cycles.
+#[derive(Clone, Copy)]
+pub enum BusWidth {
+    Bits8,
+    Bits16,
+}
+
+impl BusWidth {
+    // Size of a single bus cycle and how many of them an access of size `s`
+    // takes.
+    pub fn split(self, s: Size) -> (Size, u32) {
+        match (self, s) {
+            (BusWidth::Bits8, s) => (Size::Byte, s.value() as u32),
+            (BusWidth::Bits16, Size::Long) => (Size::Word, 2),
+            (BusWidth::Bits16, s) => (s, 1),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct MemAccess {
+    pub addr: u32,
+    pub size: Size,
+    pub value: u32,
+    pub write: bool,
+    pub fc: FunctionCode,
+}
+
+// Plain big-endian memory mapped at `base`. Anything outside of it is a bus
+// error.
+pub struct Ram {
+    pub base: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Ram {
+    pub fn new(base: u32, size: usize) -> Ram {
+        Ram {
+            base,
+            bytes: vec![0; size],
+        }
+    }
+
+    fn offset(&self, addr: u32, s: Size) -> Result<usize, BusError> {
+        let off = addr.wrapping_sub(self.base) as usize;
+        if off + s.value() as usize > self.bytes.len() {
+            return Err(BusError);
+        }
+        Ok(off)
+    }
+}
+
+impl Bus for Ram {
+    fn read(
+        &mut self,
+        addr: u32,
+        s: Size,
+        _: FunctionCode,
+    ) -> Result<u32, BusError> {
+        let off = self.offset(addr, s)?;
+        let bytes = &self.bytes[off..off + s.value() as usize];
+        Ok(bytes.iter().fold(0, |x, &b| x << 8 | b as u32))
+    }
+
+    fn write(
+        &mut self,
+        addr: u32,
+        s: Size,
+        x: u32,
+        _: FunctionCode,
+    ) -> Result<(), BusError> {
+        let off = self.offset(addr, s)?;
+        let n = s.value() as usize;
+        for (i, b) in self.bytes[off..off + n].iter_mut().enumerate() {
+            *b = (x >> (8 * (n - 1 - i))) as u8;
+        }
+        Ok(())
+    }
+}
+
+// What a protected region lets through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    ReadOnly,
+    WriteOnly,
+    Forbidden,
+}
+
+// Wraps `bus`, turning accesses forbidden by one of its regions into bus
+// errors, e.g. to make ROM unwritable.
+pub struct Protected<B> {
+    pub bus: B,
+    regions: Vec<(u32, u32, Access)>,
+}
+
+impl<B: Bus> Protected<B> {
+    pub fn new(bus: B) -> Protected<B> {
+        Protected {
+            bus,
+            regions: Vec::new(),
+        }
+    }
+
+    // Restricts the `len` bytes from `base` to `access`.
+    pub fn protect(&mut self, base: u32, len: u32, access: Access) {
+        self.regions.push((base, len, access));
+    }
+
+    fn check(&self, addr: u32, s: Size, write: bool) -> Result<(), BusError> {
+        for &(base, len, access) in &self.regions {
+            let off = addr.wrapping_sub(base);
+            let hit = off < len || base.wrapping_sub(addr) < s.value() as u32;
+            let allowed = match access {
+                Access::ReadOnly => !write,
+                Access::WriteOnly => write,
+                Access::Forbidden => false,
+            };
+            if hit && !allowed {
+                return Err(BusError);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<B: Bus> Bus for Protected<B> {
+    fn read(
+        &mut self,
+        addr: u32,
+        s: Size,
+        fc: FunctionCode,
+    ) -> Result<u32, BusError> {
+        self.check(addr, s, false)?;
+        self.bus.read(addr, s, fc)
+    }
+
+    fn write(
+        &mut self,
+        addr: u32,
+        s: Size,
+        x: u32,
+        fc: FunctionCode,
+    ) -> Result<(), BusError> {
+        self.check(addr, s, true)?;
+        self.bus.write(addr, s, x, fc)
+    }
+
+    fn set_lock(&mut self, locked: bool) {
+        self.bus.set_lock(locked)
+    }
+
+    fn reset_devices(&mut self) {
+        self.bus.reset_devices()
+    }
+
+    fn refresh_penalty(&mut self, cycle: u64) -> u32 {
+        self.bus.refresh_penalty(cycle)
+    }
+}
+
+// Runs code in place out of `rom`, mapped at `base`: program space reads
+// within it come from the slice, anything else goes to `bus`.
+pub struct SplitBus<'a, B> {
+    pub rom: &'a [u8],
+    pub base: u32,
+    pub bus: B,
+}
+
+impl<B: Bus> Bus for SplitBus<'_, B> {
+    fn read(
+        &mut self,
+        addr: u32,
+        s: Size,
+        fc: FunctionCode,
+    ) -> Result<u32, BusError> {
+        let program = matches!(
+            fc,
+            FunctionCode::UserProgram | FunctionCode::SupervisorProgram
+        );
+        let off = addr.wrapping_sub(self.base) as usize;
+        match self.rom.get(off..off + s.value() as usize) {
+            Some(bytes) if program => {
+                Ok(bytes.iter().fold(0, |x, &b| x << 8 | b as u32))
+            }
+            _ => self.bus.read(addr, s, fc),
+        }
+    }
+
+    fn write(
+        &mut self,
+        addr: u32,
+        s: Size,
+        x: u32,
+        fc: FunctionCode,
+    ) -> Result<(), BusError> {
+        self.bus.write(addr, s, x, fc)
+    }
+
+    fn set_lock(&mut self, locked: bool) {
+        self.bus.set_lock(locked)
+    }
+
+    fn reset_devices(&mut self) {
+        self.bus.reset_devices()
+    }
+
+    fn refresh_penalty(&mut self, cycle: u64) -> u32 {
+        self.bus.refresh_penalty(cycle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_only_region_rejects_writes() {
+        let mut bus = Protected::new(Ram::new(0, 0x1000));
+        bus.protect(0x100, 0x100, Access::ReadOnly);
+        let fc = FunctionCode::SupervisorData;
+        assert!(bus.write(0x1fe, Size::Long, 0x1234_5678, fc).is_err());
+        assert!(bus.write(0x0fe, Size::Long, 0x1234_5678, fc).is_err());
+        assert!(bus.write(0x200, Size::Long, 0x1234_5678, fc).is_ok());
+        assert_eq!(bus.read(0x100, Size::Long, fc).unwrap(), 0);
+        assert!(bus.bus.bytes[0xfe..0x200].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn ram_is_big_endian() {
+        let mut ram = Ram::new(0x1000, 0x10);
+        let fc = FunctionCode::SupervisorData;
+        ram.write(0x1000, Size::Long, 0x1234_5678, fc).unwrap();
+        ram.write(0x1004, Size::Word, 0x9abc, fc).unwrap();
+        assert_eq!(ram.bytes[..6], [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]);
+        assert_eq!(ram.read(0x1002, Size::Long, fc).unwrap(), 0x5678_9abc);
+        assert_eq!(ram.read(0x1001, Size::Byte, fc).unwrap(), 0x34);
+        // Below the base, and a long running past the end.
+        assert!(ram.read(0xffe, Size::Word, fc).is_err());
+        assert!(ram.write(0x100e, Size::Long, 0, fc).is_err());
+    }
+}
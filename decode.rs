@@ -0,0 +1,1537 @@
+use std::collections::HashMap;
+
+use crate::bus::Bus;
+use crate::{
+    addr_add, AccessFault, AluOp, BfOp, EffAddr, Index, MicroI, Reg, ShiftOp,
+    Size, Variant, M68K,
+};
+
+// Operation encoded by an opcode, before its operands are decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Instr {
+    Move(Size),
+    Movea(Size),
+    Moveq,
+    Add(Size),
+    Adda(Size),
+    Addx(Size),
+    Sub(Size),
+    Suba(Size),
+    Subx(Size),
+    Cmp(Size),
+    Cmpa(Size),
+    Cmpi(Size),
+    Cmpm(Size),
+    Cas(Size),
+    Cas2(Size),
+    Addq(Size),
+    Subq(Size),
+    Neg(Size),
+    Negx(Size),
+    Clr(Size),
+    Nbcd,
+    Pack,
+    Unpk,
+    Mulu,
+    Muls,
+    Divu,
+    Divs,
+    // Shifts and rotates of a data register, then of a memory word by one.
+    ShiftReg(Size),
+    ShiftMem,
+    BitField(BfOp),
+    Bra,
+    Bsr,
+    Bcc,
+    Scc,
+    Dbcc,
+    Trap(u8),
+    MoveFromSr,
+    MoveFromCcr,
+    MoveToSr,
+    // ORI, ANDI and EORI to SR.
+    OriToSr,
+    AndiToSr,
+    EoriToSr,
+    Tst(Size),
+    Tas,
+    Movec,
+    Trapv,
+    Chk,
+    Reset,
+    Stop,
+    Jsr,
+    Lea,
+    Rts,
+    Rte,
+    Move16,
+    LineA,
+    LineF,
+    Illegal,
+}
+
+// Sets of addressing modes, one bit per mode in the order of the manual:
+// Dn, An, (An), (An)+, -(An), (d16,An), (d8,An,Xn), (xxx).W, (xxx).L,
+// (d16,PC), (d8,PC,Xn), #imm.
+const EA_ALL: u16 = 0xfff;
+const EA_DATA: u16 = EA_ALL & !0x002;
+const EA_ALTERABLE: u16 = 0x1ff;
+const EA_DATA_ALT: u16 = EA_DATA & EA_ALTERABLE;
+const EA_MEM_ALT: u16 = EA_ALTERABLE & !0x003;
+const EA_CONTROL: u16 = 0x7e4;
+const EA_CONTROL_ALT: u16 = EA_CONTROL & EA_ALTERABLE;
+
+// Whether the 6-bit mode/register field `ea` is one of the modes in `set`.
+fn ea_in(ea: u16, set: u16) -> bool {
+    let (mode, reg) = (ea >> 3 & 7, ea & 7);
+    let i = match mode {
+        7 if reg > 4 => return false,
+        7 => 7 + reg,
+        _ => mode,
+    };
+    set >> i & 1 != 0
+}
+
+// The destination field of MOVE has its register and mode swapped.
+fn move_dst(opcode: u16) -> u16 {
+    (opcode >> 9 & 7) | (opcode >> 3 & 0o70)
+}
+
+fn size_field(opcode: u16) -> Option<Size> {
+    match opcode >> 6 & 3 {
+        0 => Some(Size::Byte),
+        1 => Some(Size::Word),
+        2 => Some(Size::Long),
+        _ => None,
+    }
+}
+
+impl Instr {
+    fn privileged(self) -> bool {
+        use Instr::*;
+        matches!(
+            self,
+            MoveToSr
+                | OriToSr
+                | AndiToSr
+                | EoriToSr
+                | Reset
+                | Stop
+                | Rte
+                | Movec
+        )
+    }
+}
+
+pub fn decode_instruction(opcode: u16) -> Instr {
+    use Instr::*;
+    let ea = opcode & 0o77;
+    let opmode = opcode >> 6 & 7;
+    match opcode >> 12 {
+        0x0 if opcode & 0xfdff == 0x0cfc => {
+            Cas2(if opcode & 0x200 == 0 { Size::Word } else { Size::Long })
+        }
+        0x0 if opcode & 0xf9c0 == 0x08c0
+            && opcode & 0x600 != 0
+            && ea_in(ea, EA_MEM_ALT) =>
+        {
+            Cas(match opcode >> 9 & 3 {
+                1 => Size::Byte,
+                2 => Size::Word,
+                _ => Size::Long,
+            })
+        }
+        0x0 if opcode == 0x007c => OriToSr,
+        0x0 if opcode == 0x027c => AndiToSr,
+        0x0 if opcode == 0x0a7c => EoriToSr,
+        0x0 if opcode & 0xff00 == 0x0c00 => match size_field(opcode) {
+            Some(s) if ea_in(ea, EA_DATA_ALT) => Cmpi(s),
+            _ => Illegal,
+        },
+        0x1..=0x3 => {
+            let s = match opcode >> 12 {
+                0x1 => Size::Byte,
+                0x2 => Size::Long,
+                _ => Size::Word,
+            };
+            let src_ok = ea_in(ea, EA_ALL) && (s != Size::Byte || ea >> 3 != 1);
+            if !src_ok {
+                Illegal
+            } else if opmode == 1 && s != Size::Byte {
+                Movea(s)
+            } else if ea_in(move_dst(opcode), EA_DATA_ALT) {
+                Move(s)
+            } else {
+                Illegal
+            }
+        }
+        0x4 if opcode == 0x4e70 => Reset,
+        0x4 if opcode == 0x4e72 => Stop,
+        0x4 if opcode & 0xffc0 == 0x40c0 && ea_in(ea, EA_DATA_ALT) => {
+            MoveFromSr
+        }
+        0x4 if opcode & 0xfb00 == 0x4000 && ea_in(ea, EA_DATA_ALT) => {
+            match size_field(opcode) {
+                Some(s) if opcode & 0x400 != 0 => Neg(s),
+                Some(s) => Negx(s),
+                None => Illegal,
+            }
+        }
+        0x4 if opcode & 0xffc0 == 0x42c0 && ea_in(ea, EA_DATA_ALT) => {
+            MoveFromCcr
+        }
+        0x4 if opcode & 0xffc0 == 0x4800 && ea_in(ea, EA_DATA_ALT) => Nbcd,
+        0x4 if opcode & 0xff00 == 0x4200 && ea_in(ea, EA_DATA_ALT) => {
+            size_field(opcode).map_or(Illegal, Clr)
+        }
+        0x4 if opcode & 0xffc0 == 0x46c0 && ea_in(ea, EA_DATA) => MoveToSr,
+        0x4 if opcode & 0xffc0 == 0x4ac0 && ea_in(ea, EA_DATA_ALT) => Tas,
+        // All modes are decoded, the 68000 only takes data alterable ones.
+        0x4 if opcode & 0xff00 == 0x4a00 => match size_field(opcode) {
+            Some(Size::Byte) if ea >> 3 == 1 => Illegal,
+            Some(s) if ea_in(ea, EA_ALL) => Tst(s),
+            _ => Illegal,
+        },
+        0x4 if opcode & 0xfff0 == 0x4e40 => Trap((opcode & 0xf) as u8),
+        0x4 if opcode == 0x4e73 => Rte,
+        0x4 if opcode == 0x4e75 => Rts,
+        0x4 if opcode == 0x4e76 => Trapv,
+        0x4 if opcode & 0xfffe == 0x4e7a => Movec,
+        0x4 if opcode & 0xffc0 == 0x4e80 && ea_in(ea, EA_CONTROL) => Jsr,
+        0x4 if opcode & 0xf1c0 == 0x41c0 && ea_in(ea, EA_CONTROL) => Lea,
+        0x4 if opcode & 0xf1c0 == 0x4180 && ea_in(ea, EA_DATA) => Chk,
+        0x5 if opcode >> 6 & 3 == 3 && ea >> 3 == 1 => Dbcc,
+        0x5 if opcode >> 6 & 3 == 3 && ea_in(ea, EA_DATA_ALT) => Scc,
+        0x5 => match size_field(opcode) {
+            Some(Size::Byte) if ea >> 3 == 1 => Illegal,
+            Some(s) if ea_in(ea, EA_ALTERABLE) && opcode & 0x100 == 0 => {
+                Addq(s)
+            }
+            Some(s) if ea_in(ea, EA_ALTERABLE) => Subq(s),
+            _ => Illegal,
+        },
+        0x6 => match opcode >> 8 & 0xf {
+            0 => Bra,
+            1 => Bsr,
+            _ => Bcc,
+        },
+        0x8 if opcode & 0x1f0 == 0x140 => Pack,
+        0x8 if opcode & 0x1f0 == 0x180 => Unpk,
+        // Bit 8 is zero, the register field can only name a data register.
+        0x7 if opcode & 0x100 == 0 => Moveq,
+        0x8 | 0xc if opmode & 3 == 3 && ea_in(ea, EA_DATA) => {
+            match (opcode >> 12, opmode) {
+                (0x8, 3) => Divu,
+                (0x8, _) => Divs,
+                (_, 3) => Mulu,
+                _ => Muls,
+            }
+        }
+        // ADD and SUB share their encodings, as do ADDA/SUBA and ADDX/SUBX.
+        0x9 | 0xd => {
+            let ops: [fn(Size) -> Instr; 3] = if opcode >> 12 == 0xd {
+                [Add, Adda, Addx]
+            } else {
+                [Sub, Suba, Subx]
+            };
+            let [op, opa, opx] = ops;
+            match size_field(opcode) {
+                None if ea_in(ea, EA_ALL) => {
+                    opa(if opmode == 3 { Size::Word } else { Size::Long })
+                }
+                // Bit 3 selects Dy,Dx or -(Ay),-(Ax).
+                Some(s) if opmode & 4 != 0 && ea >> 3 <= 1 => opx(s),
+                Some(s) if opmode & 4 != 0 && ea_in(ea, EA_MEM_ALT) => op(s),
+                Some(s) if opmode & 4 == 0 && ea_in(ea, EA_ALL) => {
+                    if s == Size::Byte && ea >> 3 == 1 {
+                        Illegal
+                    } else {
+                        op(s)
+                    }
+                }
+                _ => Illegal,
+            }
+        }
+        0xe if opcode & 0xf8c0 == 0xe8c0 => {
+            use BfOp::*;
+            let op = [Tst, Extu, Chg, Exts, Clr, Ffo, Set, Ins]
+                [(opcode >> 8 & 7) as usize];
+            let set = match op {
+                Chg | Clr | Set | Ins => EA_CONTROL_ALT,
+                _ => EA_CONTROL,
+            };
+            if ea >> 3 == 0 || ea_in(ea, set) {
+                BitField(op)
+            } else {
+                Illegal
+            }
+        }
+        0xe => match size_field(opcode) {
+            Some(s) => ShiftReg(s),
+            None if opcode & 0x800 == 0 && ea_in(ea, EA_MEM_ALT) => ShiftMem,
+            None => Illegal,
+        },
+        0xb => match (opmode, size_field(opcode)) {
+            (3 | 7, _) if ea_in(ea, EA_ALL) => {
+                Cmpa(if opmode == 3 { Size::Word } else { Size::Long })
+            }
+            (0..=2, Some(Size::Byte)) if ea >> 3 == 1 => Illegal,
+            (0..=2, Some(s)) if ea_in(ea, EA_ALL) => Cmp(s),
+            (4..=6, Some(s)) if ea >> 3 == 1 => Cmpm(s),
+            // EOR is not implemented yet.
+            _ => Illegal,
+        },
+        0xa => LineA,
+        0xf if opcode & 0xffe0 == 0xf600 || opcode & 0xfff8 == 0xf620 => {
+            Move16
+        }
+        0xf => LineF,
+        _ => Illegal,
+    }
+}
+
+// How the decoder classifies the whole opcode space.
+#[derive(Debug)]
+pub struct CoverageReport {
+    pub decoded: usize,
+    pub illegal: usize,
+    pub per_instr: HashMap<Instr, usize>,
+}
+
+pub fn decode_coverage() -> CoverageReport {
+    let mut report = CoverageReport {
+        decoded: 0,
+        illegal: 0,
+        per_instr: HashMap::new(),
+    };
+    for opcode in 0..=0xffff {
+        let instr = decode_instruction(opcode);
+        if instr == Instr::Illegal {
+            report.illegal += 1;
+        } else {
+            report.decoded += 1;
+        }
+        *report.per_instr.entry(instr).or_default() += 1;
+    }
+    report
+}
+
+// Broad kind of an instruction, for statistics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InstrClass {
+    Move,
+    Arithmetic,
+    Logical,
+    Shift,
+    // Program control, Scc included.
+    Branch,
+    System,
+}
+
+// The class of `opcode`, None for what does not decode to an instruction.
+pub fn opcode_class(opcode: u16) -> Option<InstrClass> {
+    use Instr::*;
+    use InstrClass as C;
+    Some(match decode_instruction(opcode) {
+        Move(_) | Movea(_) | MoveFromSr | MoveFromCcr | Move16 => C::Move,
+        Moveq | Lea => C::Move,
+        MoveToSr | OriToSr | AndiToSr | EoriToSr => C::System,
+        Add(_) | Adda(_) | Addx(_) | Sub(_) | Suba(_) | Subx(_) => {
+            C::Arithmetic
+        }
+        Cmp(_) | Cmpa(_) | Cmpi(_) | Cmpm(_) | Addq(_) | Subq(_) => {
+            C::Arithmetic
+        }
+        Neg(_) | Negx(_) | Mulu | Muls | Divu | Divs => C::Arithmetic,
+        Clr(_) | Nbcd | Tst(_) | Cas(_) | Cas2(_) => C::Arithmetic,
+        Pack | Unpk => C::Arithmetic,
+        Tas | BitField(_) => C::Logical,
+        ShiftReg(_) | ShiftMem => C::Shift,
+        Bra | Bsr | Bcc | Scc | Dbcc | Jsr | Rts => C::Branch,
+        Trap(_) | Trapv | Chk | Movec | Reset | Stop | Rte => C::System,
+        LineA | LineF | Illegal => return None,
+    })
+}
+
+impl M68K {
+    // Expands an opcode into micro-ops, fetching its extension words. Faults
+    // are reported as the exception vector to take.
+    pub fn decode_opcode(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+    ) -> Result<(), u8> {
+        use Instr::*;
+        use MicroI::*;
+        let instr = match decode_instruction(opcode) {
+            MoveFromCcr | Movec if self.variant < Variant::M68010 => Illegal,
+            BitField(_) | Cas(_) | Cas2(_) | Pack | Unpk
+                if self.variant < Variant::M68020 =>
+            {
+                Illegal
+            }
+            Tst(_)
+                if self.variant < Variant::M68020
+                    && !ea_in(opcode & 0o77, EA_DATA_ALT) =>
+            {
+                Illegal
+            }
+            instr => instr,
+        };
+        // MOVE from SR became privileged with the 68010, which added MOVE
+        // from CCR for user code.
+        let privileged = instr.privileged()
+            || instr == MoveFromSr && self.variant >= Variant::M68010;
+        if privileged && !self.supervisor() {
+            return Err(8);
+        }
+        match instr {
+            Move(s) => self.gen_move(bus, opcode, s)?,
+            Movea(s) => self.gen_movea(bus, opcode, s)?,
+            Moveq => self.add_instr(Alu {
+                op: AluOp::Move,
+                dst: Reg::D((opcode >> 9 & 7) as usize),
+                src: Reg::Immediate(opcode as i8 as i32),
+                size: Size::Long,
+                set_flags: true,
+            }),
+            Add(s) => self.gen_add(bus, opcode, AluOp::Add, s)?,
+            Sub(s) => self.gen_add(bus, opcode, AluOp::Sub, s)?,
+            Adda(s) => self.gen_adda(bus, opcode, AluOp::Add, s)?,
+            Suba(s) => self.gen_adda(bus, opcode, AluOp::Sub, s)?,
+            Cmp(s) | Cmpa(s) | Cmpi(s) | Cmpm(s) => {
+                self.gen_cmp(bus, opcode, instr, s)?
+            }
+            Cas(s) => self.gen_cas(bus, opcode, s)?,
+            Cas2(s) => self.gen_cas2(bus, s)?,
+            Addx(s) => self.gen_addx(opcode, AluOp::Addx, s),
+            Subx(s) => self.gen_addx(opcode, AluOp::Subx, s),
+            Neg(s) => self.gen_neg(bus, opcode, AluOp::Sub, s)?,
+            Negx(s) => self.gen_neg(bus, opcode, AluOp::Subx, s)?,
+            Clr(s) => self.gen_clr(bus, opcode, s)?,
+            // 0 - <ea> - X in BCD, the same read-modify-write as NEGX.
+            Nbcd => self.gen_neg(bus, opcode, AluOp::Sbcd, Size::Byte)?,
+            Pack | Unpk => self.gen_pack(bus, opcode, instr)?,
+            Addq(s) => self.gen_addq(bus, opcode, AluOp::Add, s)?,
+            Subq(s) => self.gen_addq(bus, opcode, AluOp::Sub, s)?,
+            Mulu | Muls | Divu | Divs => self.gen_muldiv(bus, opcode, instr)?,
+            ShiftReg(s) => self.gen_shift_reg(opcode, s),
+            ShiftMem => self.gen_shift_mem(bus, opcode)?,
+            BitField(op) => self.gen_bitfield(bus, opcode, op)?,
+            Bra | Bsr | Bcc => self.gen_branch(bus, opcode, instr)?,
+            Scc => self.gen_scc(bus, opcode)?,
+            Dbcc => self.gen_dbcc(bus, opcode)?,
+            Trap(n) => self.trap(bus, n)?,
+            MoveFromSr | MoveFromCcr => {
+                let ea = self.decode_effaddr(bus, opcode & 0o77, Size::Word)?;
+                let r = if instr == MoveFromSr { Reg::SR } else { Reg::CCR };
+                self.add_instr(Mov(Reg::InTmp(0), r));
+                self.store_effaddr(ea, Reg::InTmp(0), Size::Word);
+            }
+            Movec => self.gen_movec(bus, opcode)?,
+            // Writing SR swaps the stack pointers when S changes. Interrupts
+            // the new mask lets through are taken after the instruction.
+            MoveToSr => {
+                let ea = self.decode_effaddr(bus, opcode & 0o77, Size::Word)?;
+                self.load_effaddr(ea, Size::Word);
+                self.add_instr(Mov(Reg::SR, Reg::In0));
+            }
+            OriToSr | AndiToSr | EoriToSr => self.gen_logic_sr(bus, instr)?,
+            Tst(s) => {
+                let ea = self.decode_effaddr(bus, opcode & 0o77, s)?;
+                self.load_effaddr(ea, s);
+                self.add_instr(Alu {
+                    op: AluOp::Move,
+                    dst: Reg::InTmp(0),
+                    src: Reg::In0,
+                    size: s,
+                    set_flags: true,
+                });
+            }
+            Tas => self.gen_tas(bus, opcode)?,
+            Trapv => self.add_instr(TrapIf(0x9, 7)),
+            Chk => self.gen_chk(bus, opcode)?,
+            Jsr => self.gen_jsr(bus, opcode)?,
+            // Only the address is computed, the operand is never read.
+            Lea => {
+                let ea = self.decode_effaddr(bus, opcode & 0o77, Size::Long)?;
+                self.load_effaddr_noderef(ea);
+                let r = (opcode >> 9 & 7) as usize;
+                self.add_instr(Mov(Reg::A(r), Reg::In0));
+            }
+            Rts => self.gen_rts(),
+            Rte => self.gen_rte(),
+            Reset => self.add_instr(ResetDevices),
+            // The new SR is in place before the CPU stops, so a pending
+            // interrupt it unmasks wakes it up right away.
+            Stop => {
+                let sr = self.fetch_word(bus)?;
+                self.add_instr(Set(Reg::SR, sr as u32));
+                self.add_instr(StopCpu);
+            }
+            Move16 if self.variant >= Variant::M68040 => {
+                self.gen_move16(bus, opcode)?
+            }
+            Move16 | LineF => return Err(11),
+            LineA => return Err(10),
+            Illegal => return Err(4),
+        }
+        Ok(())
+    }
+
+    // Reads the extension words of the 6-bit mode/register field `ea`.
+    pub fn decode_effaddr(
+        &mut self,
+        bus: &mut impl Bus,
+        ea: u16,
+        s: Size,
+    ) -> Result<EffAddr, u8> {
+        let r = (ea & 7) as u8;
+        Ok(match ea >> 3 {
+            0 => EffAddr::DataReg { r },
+            1 => EffAddr::AddrReg { r },
+            2 => EffAddr::Addr { r },
+            3 => EffAddr::PostInc { r, s },
+            4 => EffAddr::PreDec { r, s },
+            5 => EffAddr::AddrDisp {
+                r,
+                d: self.fetch_word(bus)? as i16,
+            },
+            6 => self.decode_indexed(bus, Some(r))?,
+            _ => match r {
+                0 => EffAddr::AbsShort {
+                    addr: self.fetch_word(bus)? as i16,
+                },
+                1 => EffAddr::AbsLong {
+                    hi: self.fetch_word(bus)?,
+                    lo: self.fetch_word(bus)?,
+                },
+                2 => EffAddr::PCIndDisp {
+                    base: self.pc,
+                    d: self.fetch_word(bus)? as i16 as i32,
+                },
+                3 => self.decode_indexed(bus, None)?,
+                // Immediates follow in the instruction stream, bytes in the
+                // low half of their word.
+                4 => EffAddr::Immediate {
+                    x: match s {
+                        Size::Long => self.fetch_long(bus)?,
+                        _ => self.fetch_word(bus)? as u32 & s.mask(),
+                    },
+                },
+                _ => return Err(4),
+            },
+        })
+    }
+
+    // Indexed modes, off An or off the PC when `r` is None. The brief
+    // extension word gives an index register and an 8-bit displacement, its
+    // scale bits are new with the 68020, the 68000 and 68010 ignore them.
+    // The 68020 also has a full format, flagged by bit 8, that can suppress
+    // the base and the index, has 16 or 32-bit displacements and may go
+    // through memory.
+    fn decode_indexed(
+        &mut self,
+        bus: &mut impl Bus,
+        r: Option<u8>,
+    ) -> Result<EffAddr, u8> {
+        let pc = self.pc;
+        let ext = self.fetch_word(bus)?;
+        let n = (ext >> 12 & 7) as usize;
+        let scale = match self.variant {
+            Variant::M68000 | Variant::M68010 => 0,
+            _ => (ext >> 9 & 3) as u8,
+        };
+        let idx = Index {
+            r: if ext & 0x8000 != 0 { Reg::A(n) } else { Reg::D(n) },
+            long: ext & 0x800 != 0,
+            scale,
+        };
+        if ext & 0x100 == 0 {
+            let d = ext as i8 as i32;
+            return Ok(match r {
+                Some(r) => EffAddr::AddrIdx {
+                    r: Some(r),
+                    d,
+                    idx: Some(idx),
+                },
+                None => EffAddr::PCIndIdx {
+                    base: Some(pc),
+                    d,
+                    idx: Some(idx),
+                },
+            });
+        }
+        if self.variant < Variant::M68020 || ext & 8 != 0 {
+            return Err(4);
+        }
+        // BS and IS suppress the base and the index, the PC one leaving
+        // ZPC, a base of 0.
+        let an = r.is_some();
+        let suppress_base = ext & 0x80 != 0;
+        let r = r.filter(|_| !suppress_base);
+        let base = Some(pc).filter(|_| !suppress_base);
+        let idx = Some(idx).filter(|_| ext & 0x40 == 0);
+        let d = self.displacement(bus, ext >> 4 & 3)?;
+        // I/IS: 0 for no memory indirection, 1 to 3 for preindexed and 5 to 7
+        // for postindexed, the low two bits giving the size of od. Only the
+        // former make sense with the index suppressed.
+        let preindex = match ext & 7 {
+            0 => {
+                return Ok(if an {
+                    EffAddr::AddrIdx { r, d, idx }
+                } else {
+                    EffAddr::PCIndIdx { base, d, idx }
+                });
+            }
+            1..=3 => true,
+            5..=7 if idx.is_some() => false,
+            _ => return Err(4),
+        };
+        let od = self.displacement(bus, ext & 3)?;
+        Ok(match (an, preindex) {
+            (true, true) => EffAddr::AddrIndPreIdx { r, d, idx, od },
+            (true, false) => EffAddr::AddrIndPostIdx { r, d, idx, od },
+            (false, true) => EffAddr::PCIndPreIdx { base, d, idx, od },
+            (false, false) => EffAddr::PCIndPostIdx { base, d, idx, od },
+        })
+    }
+
+    // Base and outer displacements of the full format, `size` being 1 for
+    // none, 2 for a word and 3 for a long. 0 is reserved.
+    fn displacement(
+        &mut self,
+        bus: &mut impl Bus,
+        size: u16,
+    ) -> Result<i32, u8> {
+        match size {
+            1 => Ok(0),
+            2 => Ok(self.fetch_word(bus)? as i16 as i32),
+            3 => Ok(self.fetch_long(bus)? as i32),
+            _ => Err(4),
+        }
+    }
+
+    // Writes `src` to `ea`, without touching the flags. `src` must not be In0
+    // or In1 as they hold the computed address.
+    fn store_effaddr(&mut self, ea: EffAddr, src: Reg, s: Size) {
+        use MicroI::*;
+        match ea {
+            EffAddr::DataReg { r } => self.add_instr(Alu {
+                op: AluOp::Move,
+                dst: Reg::D(r as usize),
+                src,
+                size: s,
+                set_flags: false,
+            }),
+            EffAddr::AddrReg { r } => {
+                self.add_instr(Mov(Reg::A(r as usize), src))
+            }
+            _ => {
+                self.load_effaddr_noderef(ea);
+                // Adding the index takes 2 cycles on top of the bus accesses.
+                // The decrement of -(An) overlaps the write, unlike a read.
+                if let EffAddr::AddrIdx { .. } = ea {
+                    self.cycles += 2;
+                }
+                self.add_instr(WriteMem(Reg::In0, src, s));
+            }
+        }
+    }
+
+    fn gen_move(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+        s: Size,
+    ) -> Result<(), u8> {
+        use Reg::*;
+        // The extension words of the source come first, so it must be
+        // decoded first.
+        let src = self.decode_effaddr(bus, opcode & 0o77, s)?;
+        let dst = self.decode_effaddr(bus, move_dst(opcode), s)?;
+        // The source, including its address register update, is done with
+        // before the destination address is computed. This is what makes
+        // aliasing moves like MOVE.L (A7)+,-(A7) behave as on the 68000, and
+        // MOVE.L A0,-(A0) store A0 as it was before the decrement.
+        self.load_effaddr(src, s);
+        self.add_instr(MicroI::Alu {
+            op: AluOp::Move,
+            dst: InTmp(0),
+            src: In0,
+            size: s,
+            set_flags: true,
+        });
+        self.store_effaddr(dst, InTmp(0), s);
+        Ok(())
+    }
+
+    fn gen_movea(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+        s: Size,
+    ) -> Result<(), u8> {
+        use Reg::*;
+        let src = self.decode_effaddr(bus, opcode & 0o77, s)?;
+        self.load_effaddr_signed(src, s);
+        self.add_instr(MicroI::Mov(A((opcode >> 9 & 7) as usize), In0));
+        Ok(())
+    }
+
+    // ADD and SUB between Dn and <ea>, bit 8 telling which is the
+    // destination.
+    fn gen_add(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+        op: AluOp,
+        s: Size,
+    ) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let dn = D((opcode >> 9 & 7) as usize);
+        let ea = self.decode_effaddr(bus, opcode & 0o77, s)?;
+        if opcode & 0x100 == 0 {
+            self.load_effaddr(ea, s);
+            self.add_instr(Alu {
+                op,
+                dst: dn,
+                src: In0,
+                size: s,
+                set_flags: true,
+            });
+        } else {
+            self.load_effaddr_noderef(ea);
+            self.add_instr(RequestMem(In0, s));
+            self.add_instr(Alu {
+                op,
+                dst: IOBuffer,
+                src: dn,
+                size: s,
+                set_flags: true,
+            });
+            self.add_instr(WriteMem(In0, IOBuffer, s));
+        }
+        Ok(())
+    }
+
+    fn gen_adda(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+        op: AluOp,
+        s: Size,
+    ) -> Result<(), u8> {
+        use Reg::*;
+        let ea = self.decode_effaddr(bus, opcode & 0o77, s)?;
+        self.load_effaddr_signed(ea, s);
+        self.add_instr(MicroI::Alu {
+            op,
+            dst: A((opcode >> 9 & 7) as usize),
+            src: In0,
+            size: Size::Long,
+            set_flags: false,
+        });
+        Ok(())
+    }
+
+    // The compares subtract for the flags only, leaving X alone. CMPA
+    // compares the whole address register with the sign-extended operand.
+    // CHK <ea>,Dn traps to vector 6 with N set if Dn is negative, clear if
+    // it is above the bound. Z, V and C are undefined.
+    fn gen_chk(&mut self, bus: &mut impl Bus, opcode: u16) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let s = Size::Word;
+        let ea = self.decode_effaddr(bus, opcode & 0o77, s)?;
+        self.load_effaddr(ea, s);
+        let d = D((opcode >> 9 & 7) as usize);
+        self.add_instr(Alu {
+            op: AluOp::Move,
+            dst: InTmp(0),
+            src: d,
+            size: s,
+            set_flags: true,
+        });
+        self.add_instr(TrapIf(0xb, 6));
+        self.add_instr(Alu {
+            op: AluOp::Cmp,
+            dst: d,
+            src: In0,
+            size: s,
+            set_flags: true,
+        });
+        // Unless Dn <= bound, N is cleared by moving a zero, then it traps.
+        self.add_instr(SkipIf(0xf, 3));
+        self.add_instr(Zero(InTmp(0)));
+        self.add_instr(Alu {
+            op: AluOp::Move,
+            dst: InTmp(0),
+            src: InTmp(0),
+            size: s,
+            set_flags: true,
+        });
+        self.add_instr(TrapIf(0x0, 6));
+        Ok(())
+    }
+
+    fn gen_cmp(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+        instr: Instr,
+        s: Size,
+    ) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let reg = (opcode >> 9 & 7) as u8;
+        let (dst, src, size) = match instr {
+            Instr::Cmp(_) => {
+                let ea = self.decode_effaddr(bus, opcode & 0o77, s)?;
+                self.load_effaddr(ea, s);
+                (D(reg as usize), In0, s)
+            }
+            Instr::Cmpa(_) => {
+                let ea = self.decode_effaddr(bus, opcode & 0o77, s)?;
+                self.load_effaddr_signed(ea, s);
+                (A(reg as usize), In0, Size::Long)
+            }
+            Instr::Cmpi(_) => {
+                let imm = self.decode_effaddr(bus, 0o74, s)?;
+                let ea = self.decode_effaddr(bus, opcode & 0o77, s)?;
+                self.load_effaddr(imm, s);
+                self.add_instr(Mov(InTmp(0), In0));
+                self.load_effaddr(ea, s);
+                (In0, InTmp(0), s)
+            }
+            _ => {
+                // CMPM (Ay)+,(Ax)+
+                let y = (opcode & 7) as u8;
+                self.load_effaddr(EffAddr::PostInc { r: y, s }, s);
+                self.add_instr(Mov(InTmp(0), In0));
+                self.load_effaddr(EffAddr::PostInc { r: reg, s }, s);
+                (In0, InTmp(0), s)
+            }
+        };
+        self.add_instr(Alu {
+            op: AluOp::Cmp,
+            dst,
+            src,
+            size,
+            set_flags: true,
+        });
+        Ok(())
+    }
+
+    // ADDX and SUBX, either Dy,Dx or -(Ay),-(Ax) with y in the low bits.
+    fn gen_addx(&mut self, opcode: u16, op: AluOp, s: Size) {
+        use MicroI::*;
+        use Reg::*;
+        let (y, x) = ((opcode & 7) as u8, (opcode >> 9 & 7) as u8);
+        if opcode & 8 == 0 {
+            self.add_instr(Alu {
+                op,
+                dst: D(x as usize),
+                src: D(y as usize),
+                size: s,
+                set_flags: true,
+            });
+        } else {
+            self.load_effaddr(EffAddr::PreDec { r: y, s }, s);
+            self.add_instr(Mov(InTmp(0), In0));
+            self.load_effaddr_noderef(EffAddr::PreDec { r: x, s });
+            self.add_instr(RequestMem(In0, s));
+            self.add_instr(Alu {
+                op,
+                dst: IOBuffer,
+                src: InTmp(0),
+                size: s,
+                set_flags: true,
+            });
+            self.add_instr(WriteMem(In0, IOBuffer, s));
+        }
+    }
+
+    // NEG and NEGX subtract the operand from zero.
+    fn gen_neg(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+        op: AluOp,
+        s: Size,
+    ) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let ea = self.decode_effaddr(bus, opcode & 0o77, s)?;
+        let src = match ea {
+            EffAddr::DataReg { r } => D(r as usize),
+            _ => {
+                self.load_effaddr_noderef(ea);
+                self.add_instr(RequestMem(In0, s));
+                IOBuffer
+            }
+        };
+        self.add_instr(Zero(InTmp(0)));
+        self.add_instr(Alu {
+            op,
+            dst: InTmp(0),
+            src,
+            size: s,
+            set_flags: true,
+        });
+        match src {
+            IOBuffer => self.add_instr(WriteMem(In0, InTmp(0), s)),
+            _ => self.add_instr(Alu {
+                op: AluOp::Move,
+                dst: src,
+                src: InTmp(0),
+                size: s,
+                set_flags: false,
+            }),
+        }
+        Ok(())
+    }
+
+    // The 68000 reads the operand before clearing it, which devices may
+    // notice. The 68010 only writes it.
+    fn gen_clr(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+        s: Size,
+    ) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let ea = self.decode_effaddr(bus, opcode & 0o77, s)?;
+        let dst = match ea {
+            EffAddr::DataReg { r } => D(r as usize),
+            _ => {
+                self.load_effaddr_noderef(ea);
+                if self.variant == Variant::M68000 {
+                    self.add_instr(RequestMem(In0, s));
+                }
+                InTmp(0)
+            }
+        };
+        self.add_instr(Zero(InTmp(0)));
+        self.add_instr(Alu {
+            op: AluOp::Move,
+            dst,
+            src: InTmp(0),
+            size: s,
+            set_flags: true,
+        });
+        if dst == InTmp(0) {
+            self.add_instr(WriteMem(In0, InTmp(0), s));
+        }
+        Ok(())
+    }
+
+    // ADDQ and SUBQ, the 3-bit immediate encodes 1 to 8. An address register
+    // is always updated whole and without touching the flags, whatever the
+    // size.
+    fn gen_addq(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+        op: AluOp,
+        s: Size,
+    ) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let q = match opcode >> 9 & 7 {
+            0 => 8,
+            q => q as i32,
+        };
+        match self.decode_effaddr(bus, opcode & 0o77, s)? {
+            EffAddr::DataReg { r } => self.add_instr(Alu {
+                op,
+                dst: D(r as usize),
+                src: Immediate(q),
+                size: s,
+                set_flags: true,
+            }),
+            EffAddr::AddrReg { r } => {
+                let q = if op == AluOp::Sub { -q } else { q };
+                self.add_instr(addr_add(A(r as usize), Immediate(q)));
+            }
+            ea => {
+                self.load_effaddr_noderef(ea);
+                self.add_instr(RequestMem(In0, s));
+                self.add_instr(Alu {
+                    op,
+                    dst: IOBuffer,
+                    src: Immediate(q),
+                    size: s,
+                    set_flags: true,
+                });
+                self.add_instr(WriteMem(In0, IOBuffer, s));
+            }
+        }
+        Ok(())
+    }
+
+    // PACK and UNPK between Dx and Dy or -(Ax) and -(Ay), with x in the low
+    // bits. The adjustment word is added to the unpacked form, before
+    // packing or after unpacking. Memory operands are read and written a
+    // byte at a time, the low byte first.
+    fn gen_pack(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+        instr: Instr,
+    ) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let (x, y) = ((opcode & 7) as u8, (opcode >> 9 & 7) as u8);
+        let adj = Immediate(self.fetch_word(bus)? as i32);
+        let op = if instr == Instr::Pack {
+            AluOp::Pack
+        } else {
+            AluOp::Unpk
+        };
+        let alu = |op, dst, src, size| Alu {
+            op,
+            dst,
+            src,
+            size,
+            set_flags: false,
+        };
+        let (src, dst) = if opcode & 8 == 0 {
+            (EffAddr::DataReg { r: x }, EffAddr::DataReg { r: y })
+        } else {
+            let s = Size::Byte;
+            (EffAddr::PreDec { r: x, s }, EffAddr::PreDec { r: y, s })
+        };
+        self.load_effaddr(src, Size::Byte);
+        self.add_instr(Mov(InTmp(0), In0));
+        if op == AluOp::Pack && opcode & 8 != 0 {
+            self.load_effaddr(src, Size::Byte);
+            self.add_instr(Scale(In0, 8));
+            self.add_instr(alu(AluOp::Or, InTmp(0), In0, Size::Word));
+        }
+        self.add_instr(alu(op, InTmp(0), adj, Size::Word));
+        if op == AluOp::Pack {
+            self.store_effaddr(dst, InTmp(0), Size::Byte);
+        } else if opcode & 8 == 0 {
+            self.store_effaddr(dst, InTmp(0), Size::Word);
+        } else {
+            self.store_effaddr(dst, InTmp(0), Size::Byte);
+            self.add_instr(Unscale(InTmp(0), 8));
+            self.store_effaddr(dst, InTmp(0), Size::Byte);
+        }
+        Ok(())
+    }
+
+    fn gen_muldiv(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+        instr: Instr,
+    ) -> Result<(), u8> {
+        use Reg::*;
+        let ea = self.decode_effaddr(bus, opcode & 0o77, Size::Word)?;
+        self.load_effaddr(ea, Size::Word);
+        let (dst, src) = (D((opcode >> 9 & 7) as usize), In0);
+        self.add_instr(match instr {
+            Instr::Mulu | Instr::Muls => MicroI::Mul {
+                signed: instr == Instr::Muls,
+                dst,
+                src,
+            },
+            _ => MicroI::Div {
+                signed: instr == Instr::Divs,
+                dst,
+                src,
+            },
+        });
+        Ok(())
+    }
+
+    // The shift kind and direction, in the same place for both forms.
+    fn shift_op(kind: u16, left: bool) -> ShiftOp {
+        use ShiftOp::*;
+        match (kind & 3, left) {
+            (0, false) => Asr,
+            (0, true) => Asl,
+            (1, false) => Lsr,
+            (1, true) => Lsl,
+            (2, false) => Roxr,
+            (2, true) => Roxl,
+            (3, false) => Ror,
+            _ => Rol,
+        }
+    }
+
+    // Shifts of Dn by an immediate count of 1 to 8 or by another register.
+    fn gen_shift_reg(&mut self, opcode: u16, s: Size) {
+        use Reg::*;
+        let op = M68K::shift_op(opcode >> 3, opcode & 0x100 != 0);
+        let field = opcode >> 9 & 7;
+        let count = if opcode & 0x20 != 0 {
+            D(field as usize)
+        } else if field == 0 {
+            Immediate(8)
+        } else {
+            Immediate(field as i32)
+        };
+        self.add_instr(MicroI::Shift {
+            op,
+            dst: D((opcode & 7) as usize),
+            count,
+            size: s,
+        });
+    }
+
+    fn gen_shift_mem(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+    ) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let op = M68K::shift_op(opcode >> 9, opcode & 0x100 != 0);
+        let ea = self.decode_effaddr(bus, opcode & 0o77, Size::Word)?;
+        self.load_effaddr_noderef(ea);
+        self.add_instr(RequestMem(In0, Size::Word));
+        self.add_instr(Shift {
+            op,
+            dst: IOBuffer,
+            count: Immediate(1),
+            size: Size::Word,
+        });
+        self.add_instr(WriteMem(In0, IOBuffer, Size::Word));
+        Ok(())
+    }
+
+    // Bcc, BRA and BSR. A zero 8-bit displacement means a 16-bit one follows,
+    // so branching to the next instruction takes the word form. From the
+    // 68020 on, $FF means a 32-bit one follows. All are relative to the word
+    // following the opcode.
+    fn gen_branch(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+        instr: Instr,
+    ) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let base = self.pc;
+        let d = match opcode as u8 {
+            0 => self.fetch_word(bus)? as i16 as i32,
+            0xff if self.variant >= Variant::M68020 => {
+                self.fetch_long(bus)? as i32
+            }
+            // 32-bit displacements are new with the 68020.
+            0xff => return Err(4),
+            d => d as i8 as i32,
+        };
+        let target = Immediate(base.wrapping_add(d as u32) as i32);
+        if instr == Instr::Bsr {
+            self.add_instr(addr_add(A(7), Immediate(-4)));
+            self.add_instr(WriteMem(A(7), PC, Size::Long));
+        }
+        let cc = match instr {
+            Instr::Bcc => (opcode >> 8 & 0xf) as u8,
+            _ => 0,
+        };
+        self.add_instr(BranchIf(cc, target));
+        Ok(())
+    }
+
+    fn gen_jsr(&mut self, bus: &mut impl Bus, opcode: u16) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let ea = self.decode_effaddr(bus, opcode & 0o77, Size::Long)?;
+        self.load_effaddr_noderef(ea);
+        self.add_instr(addr_add(A(7), Immediate(-4)));
+        self.add_instr(WriteMem(A(7), PC, Size::Long));
+        self.add_instr(Mov(PC, In0));
+        Ok(())
+    }
+
+    fn gen_rts(&mut self) {
+        use MicroI::*;
+        use Reg::*;
+        self.add_instr(RequestMem(A(7), Size::Long));
+        self.add_instr(addr_add(A(7), Immediate(4)));
+        self.add_instr(Mov(PC, IOBuffer));
+    }
+
+    // The frame is only popped once it has been read whole, and SR is
+    // restored last as it may switch to the user stack.
+    pub fn gen_rte(&mut self) {
+        use MicroI::*;
+        use Reg::*;
+        self.add_instr(Mov(In0, A(7)));
+        self.add_instr(RequestMem(In0, Size::Word));
+        self.add_instr(Mov(InTmp(0), IOBuffer));
+        self.add_instr(addr_add(In0, Immediate(2)));
+        self.add_instr(RequestMem(In0, Size::Long));
+        self.add_instr(Mov(InTmp(1), IOBuffer));
+        if self.variant >= Variant::M68010 {
+            self.add_instr(addr_add(In0, Immediate(4)));
+            self.add_instr(RequestMem(In0, Size::Word));
+            self.add_instr(PopFrame(IOBuffer));
+        } else {
+            self.add_instr(addr_add(A(7), Immediate(6)));
+        }
+        self.add_instr(Mov(PC, InTmp(1)));
+        self.add_instr(Mov(SR, InTmp(0)));
+    }
+
+    // BFxxx. Offset and width registers are read now, which tells how many
+    // bytes a field in memory covers. They go through InTmp(3) onwards.
+    fn gen_bitfield(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+        op: BfOp,
+    ) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let ext = self.fetch_word(bus)?;
+        let ea = self.decode_effaddr(bus, opcode & 0o77, Size::Byte)?;
+        let offset = if ext & 0x800 != 0 {
+            self.data_r[(ext >> 6 & 7) as usize]
+        } else {
+            (ext >> 6 & 0x1f) as u32
+        };
+        let width = if ext & 0x20 != 0 {
+            self.data_r[(ext & 7) as usize]
+        } else {
+            ext as u32
+        };
+        // A width of 0 means 32.
+        let width = (width.wrapping_sub(1) & 31) + 1;
+        let data = match ea {
+            EffAddr::DataReg { r } => Some(r as usize),
+            _ => None,
+        };
+        let pos = if data.is_some() { offset & 31 } else { offset & 7 };
+        let bf = BitField {
+            op,
+            data,
+            pos,
+            width,
+            offset,
+            reg: (ext >> 12 & 7) as usize,
+        };
+        if data.is_some() {
+            self.add_instr(bf);
+            return Ok(());
+        }
+        // Fetch the bytes of the field in front of the operation, and store
+        // them back after it when it changes them.
+        let n = (pos + width).div_ceil(8) as usize;
+        self.load_effaddr_noderef(ea);
+        self.add_instr(addr_add(In0, Immediate(offset as i32 >> 3)));
+        for i in 0..n {
+            self.add_instr(RequestMem(In0, Size::Byte));
+            self.add_instr(Mov(InTmp(3 + i), IOBuffer));
+            self.add_instr(addr_add(In0, Immediate(1)));
+        }
+        self.add_instr(bf);
+        if matches!(op, BfOp::Chg | BfOp::Clr | BfOp::Set | BfOp::Ins) {
+            self.add_instr(addr_add(In0, Immediate(-(n as i32))));
+            for i in 0..n {
+                self.add_instr(WriteMem(In0, InTmp(3 + i), Size::Byte));
+                self.add_instr(addr_add(In0, Immediate(1)));
+            }
+        }
+        Ok(())
+    }
+
+    // CAS compares Dc with the operand, then stores Du into it if they are
+    // equal or loads it into Dc otherwise, all under the bus lock.
+    fn gen_cas(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+        s: Size,
+    ) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let ext = self.fetch_word(bus)?;
+        let (dc, du) = (D((ext & 7) as usize), D((ext >> 6 & 7) as usize));
+        let ea = self.decode_effaddr(bus, opcode & 0o77, s)?;
+        self.load_effaddr_noderef(ea);
+        self.add_instr(Lock(true));
+        self.add_instr(RequestMem(In0, s));
+        self.add_instr(Alu {
+            op: AluOp::Cmp,
+            dst: IOBuffer,
+            src: dc,
+            size: s,
+            set_flags: true,
+        });
+        self.add_instr(SkipIf(0x6, 1));
+        self.add_instr(WriteMem(In0, du, s));
+        self.add_instr(SkipIf(0x7, 1));
+        self.add_instr(Alu {
+            op: AluOp::Move,
+            dst: dc,
+            src: IOBuffer,
+            size: s,
+            set_flags: false,
+        });
+        self.add_instr(Lock(false));
+        Ok(())
+    }
+
+    // CAS2 does the same on two operands addressed by registers, only
+    // storing when both compare equal.
+    fn gen_cas2(&mut self, bus: &mut impl Bus, s: Size) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let mut operands = Vec::new();
+        for i in 0..2 {
+            let ext = self.fetch_word(bus)?;
+            let n = (ext >> 12 & 7) as usize;
+            let rn = if ext & 0x8000 != 0 { A(n) } else { D(n) };
+            let (dc, du) = (D((ext & 7) as usize), D((ext >> 6 & 7) as usize));
+            // InTmp(2 + i) holds the address and InTmp(i) the operand.
+            self.add_instr(Mov(InTmp(2 + i), rn));
+            operands.push((dc, du));
+        }
+        self.add_instr(Lock(true));
+        for i in 0..2 {
+            self.add_instr(RequestMem(InTmp(2 + i), s));
+            self.add_instr(Mov(InTmp(i), IOBuffer));
+        }
+        for (i, &(dc, _)) in operands.iter().enumerate() {
+            if i == 1 {
+                self.add_instr(SkipIf(0x6, 1));
+            }
+            self.add_instr(Alu {
+                op: AluOp::Cmp,
+                dst: InTmp(i),
+                src: dc,
+                size: s,
+                set_flags: true,
+            });
+        }
+        self.add_instr(SkipIf(0x6, 2));
+        for (i, &(_, du)) in operands.iter().enumerate() {
+            self.add_instr(WriteMem(InTmp(2 + i), du, s));
+        }
+        self.add_instr(SkipIf(0x7, 2));
+        for (i, &(dc, _)) in operands.iter().enumerate() {
+            self.add_instr(Alu {
+                op: AluOp::Move,
+                dst: dc,
+                src: InTmp(i),
+                size: s,
+                set_flags: false,
+            });
+        }
+        self.add_instr(Lock(false));
+        Ok(())
+    }
+
+    // TAS tests a byte and sets its bit 7, in one locked read-modify-write
+    // when it is in memory.
+    fn gen_tas(&mut self, bus: &mut impl Bus, opcode: u16) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let ea = self.decode_effaddr(bus, opcode & 0o77, Size::Byte)?;
+        let r = match ea {
+            EffAddr::DataReg { r } => D(r as usize),
+            _ => IOBuffer,
+        };
+        if r == IOBuffer {
+            self.load_effaddr_noderef(ea);
+            self.add_instr(Lock(true));
+            self.add_instr(RequestMem(In0, Size::Byte));
+        }
+        self.add_instr(Alu {
+            op: AluOp::Move,
+            dst: InTmp(0),
+            src: r,
+            size: Size::Byte,
+            set_flags: true,
+        });
+        self.add_instr(Alu {
+            op: AluOp::Or,
+            dst: r,
+            src: Immediate(0x80),
+            size: Size::Byte,
+            set_flags: false,
+        });
+        if r == IOBuffer {
+            self.add_instr(WriteMem(In0, IOBuffer, Size::Byte));
+            self.add_instr(Lock(false));
+        }
+        Ok(())
+    }
+
+    // MOVEC between a general register and a control register, the direction
+    // being bit 0 of the opcode.
+    fn gen_movec(&mut self, bus: &mut impl Bus, opcode: u16) -> Result<(), u8> {
+        let ext = self.fetch_word(bus)?;
+        let n = (ext >> 12 & 7) as usize;
+        let r = if ext & 0x8000 != 0 { Reg::A(n) } else { Reg::D(n) };
+        let m68020 = self.variant >= Variant::M68020;
+        let ctrl = match ext & 0xfff {
+            0x000 => Reg::SFC,
+            0x001 => Reg::DFC,
+            0x800 => Reg::USP,
+            0x801 => Reg::VBR,
+            0x002 if m68020 => Reg::CACR,
+            0x802 if m68020 => Reg::CAAR,
+            0x803 if m68020 => Reg::MSP,
+            0x804 if m68020 => Reg::ISP,
+            _ => return Err(4),
+        };
+        self.add_instr(if opcode & 1 == 0 {
+            MicroI::Mov(r, ctrl)
+        } else {
+            MicroI::Mov(ctrl, r)
+        });
+        Ok(())
+    }
+
+    // ORI, ANDI and EORI to SR, with the immediate word following the
+    // opcode. Like MOVE to SR, a lowered mask lets pending interrupts in
+    // after the instruction.
+    fn gen_logic_sr(
+        &mut self,
+        bus: &mut impl Bus,
+        instr: Instr,
+    ) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let imm = self.fetch_word(bus)?;
+        let op = match instr {
+            Instr::OriToSr => AluOp::Or,
+            Instr::AndiToSr => AluOp::And,
+            _ => AluOp::Eor,
+        };
+        self.add_instr(Mov(InTmp(0), SR));
+        self.add_instr(Alu {
+            op,
+            dst: InTmp(0),
+            src: Immediate(imm as i32),
+            size: Size::Word,
+            set_flags: false,
+        });
+        self.add_instr(Mov(SR, InTmp(0)));
+        Ok(())
+    }
+
+    fn gen_scc(&mut self, bus: &mut impl Bus, opcode: u16) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        let cc = (opcode >> 8 & 0xf) as u8;
+        match self.decode_effaddr(bus, opcode & 0o77, Size::Byte)? {
+            EffAddr::DataReg { r } => self.add_instr(SetIf(cc, D(r as usize))),
+            ea => {
+                self.load_effaddr_noderef(ea);
+                self.add_instr(SetIf(cc, InTmp(0)));
+                self.add_instr(WriteMem(In0, InTmp(0), Size::Byte));
+            }
+        }
+        Ok(())
+    }
+
+    fn gen_dbcc(&mut self, bus: &mut impl Bus, opcode: u16) -> Result<(), u8> {
+        let base = self.pc;
+        let d = self.fetch_word(bus)? as i16 as u32;
+        let target = Reg::Immediate(base.wrapping_add(d) as i32);
+        let cc = (opcode >> 8 & 0xf) as u8;
+        let dn = Reg::D((opcode & 7) as usize);
+        self.add_instr(MicroI::DecBranch(cc, dn, target));
+        Ok(())
+    }
+
+    fn gen_move16(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: u16,
+    ) -> Result<(), u8> {
+        use Reg::*;
+        let r = (opcode & 7) as usize;
+        if opcode & 0o70 == 0o40 {
+            // MOVE16 (Ax)+,(Ay)+
+            let y = (self.fetch_word(bus)? >> 12 & 7) as usize;
+            self.move16(A(r), A(y))?;
+            self.add_instr(addr_add(A(r), Immediate(16)));
+            self.add_instr(addr_add(A(y), Immediate(16)));
+        } else {
+            // MOVE16 between (Ay) or (Ay)+ and an absolute address
+            let abs = Immediate(self.fetch_long(bus)? as i32);
+            if opcode & 0o10 == 0 {
+                self.move16(A(r), abs)?;
+            } else {
+                self.move16(abs, A(r))?;
+            }
+            if opcode & 0o20 == 0 {
+                self.add_instr(addr_add(A(r), Immediate(16)));
+            }
+        }
+        Ok(())
+    }
+
+    // Copies the 16-byte line at `src` to `dst`. Both must be line aligned.
+    fn move16(&mut self, src: Reg, dst: Reg) -> Result<(), u8> {
+        use MicroI::*;
+        use Reg::*;
+        // The address error frame points at the first misaligned line.
+        let (from, to) = (self.read_reg(src), self.read_reg(dst));
+        if (from | to) & 0xf != 0 {
+            let write = from & 0xf == 0;
+            self.fault = AccessFault {
+                addr: if write { to } else { from },
+                write,
+                fc: self.space(false),
+            };
+            return Err(3);
+        }
+        for i in 0..4 {
+            self.add_instr(Mov(In0, src));
+            self.add_instr(addr_add(In0, Immediate(4 * i as i32)));
+            self.add_instr(RequestMem(In0, Size::Long));
+            self.add_instr(Mov(InTmp(i), IOBuffer));
+        }
+        for i in 0..4 {
+            self.add_instr(Mov(In0, dst));
+            self.add_instr(addr_add(In0, Immediate(4 * i as i32)));
+            self.add_instr(WriteMem(In0, InTmp(i), Size::Long));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coverage() {
+        let report = decode_coverage();
+        assert!(report.decoded > 0);
+        assert_eq!(report.decoded + report.illegal, 0x10000);
+        assert_eq!(report.per_instr[&Instr::Illegal], report.illegal);
+        // eor.b d0,d1, and.w d1,d0, or.w d1,d0, abcd d1,d0 and exg d0,d1
+        // are not implemented yet.
+        for opcode in [0xb101, 0xc041, 0x8041, 0xc101, 0xc141] {
+            assert_eq!(decode_instruction(opcode), Instr::Illegal);
+        }
+    }
+
+    #[test]
+    fn classes() {
+        for (opcode, class) in [
+            (0x3200, InstrClass::Move),       // move.w d0,d1
+            (0xd240, InstrClass::Arithmetic), // add.w d0,d1
+            (0x4ac0, InstrClass::Logical),    // tas d0
+            (0xe348, InstrClass::Shift),      // lsl.w #1,d0
+            (0x6002, InstrClass::Branch),     // bra.s *+4
+            (0x4e40, InstrClass::System),     // trap #0
+        ] {
+            assert_eq!(opcode_class(opcode), Some(class), "{:04x}", opcode);
+        }
+        assert_eq!(opcode_class(0xa000), None);
+    }
+}
@@ -0,0 +1,803 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::bus::{Bus, BusError, FunctionCode};
+use crate::decode::{decode_instruction, Instr};
+use crate::{BfOp, Size, Variant};
+
+pub const CONDITIONS: [&str; 16] = [
+    "t", "f", "hi", "ls", "cc", "cs", "ne", "eq", "vc", "vs", "pl", "mi", "ge",
+    "lt", "gt", "le",
+];
+
+// Renders the instruction at `addr` in Motorola syntax, as `variant` decodes
+// it, along with its length in bytes. Branch, call and absolute targets found
+// in `symbols` are shown by name.
+pub fn disassemble(
+    bus: &mut impl Bus,
+    addr: u32,
+    symbols: Option<&HashMap<u32, String>>,
+    variant: Variant,
+) -> Result<(String, u32), BusError> {
+    let mut d = Disasm {
+        bus,
+        pc: addr,
+        variant,
+    };
+    let instr = d.instr()?;
+    Ok((instr.render(symbols), d.pc.wrapping_sub(addr)))
+}
+
+pub fn decode_at(
+    bus: &mut impl Bus,
+    addr: u32,
+    variant: Variant,
+) -> Result<DecodedInstr, BusError> {
+    let mut d = Disasm {
+        bus,
+        pc: addr,
+        variant,
+    };
+    d.instr()
+}
+
+// Decodes the instruction made of `words`, as fetched from `addr`. None if
+// they are not all of it.
+pub fn decode_words(
+    addr: u32,
+    words: &[u16],
+    variant: Variant,
+) -> Option<DecodedInstr> {
+    let mut bus = Words { base: addr, words };
+    let mut d = Disasm {
+        bus: &mut bus,
+        pc: addr,
+        variant,
+    };
+    d.instr().ok()
+}
+
+// Serves word reads out of `words` placed at `base`, anything else being a
+// bus error.
+struct Words<'a> {
+    base: u32,
+    words: &'a [u16],
+}
+
+impl Bus for Words<'_> {
+    fn read(
+        &mut self,
+        addr: u32,
+        s: Size,
+        _: FunctionCode,
+    ) -> Result<u32, BusError> {
+        let off = addr.wrapping_sub(self.base);
+        match self.words.get(off as usize / 2) {
+            Some(&w) if s == Size::Word && off & 1 == 0 => Ok(w as u32),
+            _ => Err(BusError),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _: u32,
+        _: Size,
+        _: u32,
+        _: FunctionCode,
+    ) -> Result<(), BusError> {
+        Err(BusError)
+    }
+}
+
+// An instruction as its mnemonic, without the size suffix, and operands.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedInstr {
+    pub addr: u32,
+    pub mnemonic: String,
+    pub size: Option<Size>,
+    pub operands: Vec<Operand>,
+}
+
+// An operand for tools to look at. PC-relative operands and branches give
+// the address they designate and absolute short addresses are
+// sign-extended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    DataReg(u8),
+    AddrReg(u8),
+    Indirect(u8),
+    // (Dn), only found in CAS2.
+    DataIndirect(u8),
+    PostInc(u8),
+    PreDec(u8),
+    Disp {
+        r: u8,
+        d: i32,
+    },
+    // The full format extension word of the 68020 may suppress the base and
+    // the index, and go through memory.
+    Indexed {
+        base: Base,
+        d: i32,
+        idx: Option<IndexReg>,
+    },
+    MemIndirect {
+        base: Base,
+        d: i32,
+        idx: Option<IndexReg>,
+        od: i32,
+        preindex: bool,
+    },
+    PcRelative(u32),
+    AbsShort(u32),
+    AbsLong(u32),
+    Immediate(u32),
+    // Constants held in the opcode, as for MOVEQ, ADDQ and TRAP.
+    Quick(i32),
+    // Where a branch goes.
+    Target(u32),
+    Sr,
+    Ccr,
+    // A MOVEC control register, by its code.
+    Control(u16),
+    // Register pairs of CAS2.
+    Pair(Box<Operand>, Box<Operand>),
+    BitField {
+        ea: Box<Operand>,
+        offset: BfArg,
+        width: BfArg,
+    },
+    // The opcode of something that is no instruction, for dc.w.
+    Raw(u16),
+}
+
+// Base register of the indexed modes. The PC one holds the address of the
+// extension word, which the displacement is relative to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Base {
+    A(u8),
+    Pc(u32),
+    // An suppressed, or ZPC when it is the PC that is.
+    Suppressed,
+    Zpc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexReg {
+    pub addr: bool,
+    pub r: u8,
+    pub long: bool,
+    pub scale: u8,
+}
+
+// Bit-field offsets and widths, either immediate or in a data register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BfArg {
+    Imm(u32),
+    Reg(u8),
+}
+
+impl fmt::Display for IndexReg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.addr { 'a' } else { 'd' };
+        let size = if self.long { 'l' } else { 'w' };
+        write!(f, "{}{}.{}", kind, self.r, size)?;
+        if self.scale != 0 {
+            write!(f, "*{}", 1 << self.scale)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for BfArg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BfArg::Imm(n) => write!(f, "{}", n),
+            BfArg::Reg(r) => write!(f, "d{}", r),
+        }
+    }
+}
+
+impl DecodedInstr {
+    // Motorola syntax, addresses found in `symbols` shown by name.
+    pub fn render(&self, symbols: Option<&HashMap<u32, String>>) -> String {
+        let mut text = self.mnemonic.clone();
+        if let Some(s) = self.size {
+            text += suffix(s);
+        }
+        let operands: Vec<String> =
+            self.operands.iter().map(|o| o.render(symbols)).collect();
+        if !operands.is_empty() {
+            text += " ";
+            text += &operands.join(",");
+        }
+        text
+    }
+}
+
+impl Operand {
+    fn render(&self, symbols: Option<&HashMap<u32, String>>) -> String {
+        let name = |addr: u32| symbols.and_then(|s| s.get(&addr));
+        let target = |addr: u32| match name(addr) {
+            Some(name) => name.clone(),
+            None => format!("${:x}", addr),
+        };
+        // The displacement of an indexed mode, as an address when off the
+        // PC, and what the parentheses hold.
+        let parts = |base: &Base, d: i32| match *base {
+            Base::A(r) => (signed_hex(d), Some(format!("a{}", r))),
+            Base::Pc(pc) => {
+                let addr = pc.wrapping_add(d as u32);
+                (target(addr), Some("pc".to_string()))
+            }
+            Base::Suppressed => (signed_hex(d), None),
+            Base::Zpc => (signed_hex(d), Some("zpc".to_string())),
+        };
+        let list = |items: Vec<Option<String>>| {
+            let items: Vec<String> = items.into_iter().flatten().collect();
+            items.join(",")
+        };
+        match self {
+            Operand::DataReg(r) => format!("d{}", r),
+            Operand::AddrReg(r) => format!("a{}", r),
+            Operand::Indirect(r) => format!("(a{})", r),
+            Operand::DataIndirect(r) => format!("(d{})", r),
+            Operand::PostInc(r) => format!("(a{})+", r),
+            Operand::PreDec(r) => format!("-(a{})", r),
+            Operand::Disp { r, d } => format!("{}(a{})", signed_hex(*d), r),
+            Operand::Indexed { base, d, idx } => match (parts(base, *d), idx) {
+                ((d, Some(base)), Some(idx)) => {
+                    format!("{}({},{})", d, base, idx)
+                }
+                ((d, base), idx) => {
+                    let idx = idx.map(|i| i.to_string());
+                    format!("({})", list(vec![Some(d), base, idx]))
+                }
+            },
+            Operand::MemIndirect {
+                base,
+                d,
+                idx,
+                od,
+                preindex,
+            } => {
+                let (d, base) = parts(base, *d);
+                let idx = idx.map(|i| i.to_string());
+                let od = (*od != 0).then(|| signed_hex(*od));
+                let (inner, outer) = match preindex {
+                    true => (vec![Some(d), base, idx], None),
+                    false => (vec![Some(d), base], idx),
+                };
+                let memory = format!("[{}]", list(inner));
+                format!("({})", list(vec![Some(memory), outer, od]))
+            }
+            Operand::PcRelative(addr) => format!("{}(pc)", target(*addr)),
+            Operand::AbsShort(addr) => match name(*addr) {
+                Some(name) => format!("{}.w", name),
+                None => format!("${:x}.w", *addr as u16),
+            },
+            Operand::AbsLong(addr) | Operand::Target(addr) => target(*addr),
+            Operand::Immediate(x) => format!("#${:x}", x),
+            Operand::Quick(n) => format!("#{}", n),
+            Operand::Sr => "sr".to_string(),
+            Operand::Ccr => "ccr".to_string(),
+            Operand::Control(c) => match c {
+                0x000 => "sfc".to_string(),
+                0x001 => "dfc".to_string(),
+                0x002 => "cacr".to_string(),
+                0x800 => "usp".to_string(),
+                0x801 => "vbr".to_string(),
+                0x802 => "caar".to_string(),
+                0x803 => "msp".to_string(),
+                0x804 => "isp".to_string(),
+                c => format!("${:x}", c),
+            },
+            Operand::Pair(a, b) => {
+                format!("{}:{}", a.render(symbols), b.render(symbols))
+            }
+            Operand::BitField { ea, offset, width } => {
+                format!("{}{{{}:{}}}", ea.render(symbols), offset, width)
+            }
+            Operand::Raw(x) => format!("${:04x}", x),
+        }
+    }
+}
+
+struct Disasm<'a, B> {
+    bus: &'a mut B,
+    pc: u32,
+    variant: Variant,
+}
+
+pub fn suffix(s: Size) -> &'static str {
+    match s {
+        Size::Byte => ".b",
+        Size::Word => ".w",
+        Size::Long => ".l",
+    }
+}
+
+fn signed_hex(x: i32) -> String {
+    if x < 0 {
+        format!("-${:x}", -(x as i64))
+    } else {
+        format!("${:x}", x)
+    }
+}
+
+fn pair(a: Operand, b: Operand) -> Operand {
+    Operand::Pair(Box::new(a), Box::new(b))
+}
+
+impl<B: Bus> Disasm<'_, B> {
+    fn word(&mut self) -> Result<u16, BusError> {
+        let fc = FunctionCode::SupervisorProgram;
+        let x = self.bus.read(self.pc, Size::Word, fc)?;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(x as u16)
+    }
+
+    fn long(&mut self) -> Result<u32, BusError> {
+        let hi = self.word()? as u32;
+        Ok(hi << 16 | self.word()? as u32)
+    }
+
+    // The indexed modes off `base`, from a brief or a full format extension
+    // word.
+    fn indexed(&mut self, base: Base) -> Result<Operand, BusError> {
+        let ext = self.word()?;
+        let idx = IndexReg {
+            addr: ext & 0x8000 != 0,
+            r: (ext >> 12 & 7) as u8,
+            long: ext & 0x800 != 0,
+            scale: (ext >> 9 & 3) as u8,
+        };
+        if ext & 0x100 == 0 {
+            let d = ext as i8 as i32;
+            return Ok(Operand::Indexed {
+                base,
+                d,
+                idx: Some(idx),
+            });
+        }
+        let base = match base {
+            _ if ext & 0x80 == 0 => base,
+            Base::Pc(_) => Base::Zpc,
+            _ => Base::Suppressed,
+        };
+        let idx = Some(idx).filter(|_| ext & 0x40 == 0);
+        let d = self.displacement(ext >> 4 & 3)?;
+        if ext & 7 == 0 {
+            return Ok(Operand::Indexed { base, d, idx });
+        }
+        let od = self.displacement(ext & 3)?;
+        Ok(Operand::MemIndirect {
+            base,
+            d,
+            idx,
+            od,
+            preindex: ext & 4 == 0,
+        })
+    }
+
+    // A null, word or long displacement of the full format.
+    fn displacement(&mut self, size: u16) -> Result<i32, BusError> {
+        Ok(match size {
+            2 => self.word()? as i16 as i32,
+            3 => self.long()? as i32,
+            _ => 0,
+        })
+    }
+
+    // The 6-bit mode/register field `ea` of an `s`-sized operand.
+    fn ea(&mut self, ea: u16, s: Size) -> Result<Operand, BusError> {
+        let r = (ea & 7) as u8;
+        Ok(match ea >> 3 {
+            0 => Operand::DataReg(r),
+            1 => Operand::AddrReg(r),
+            2 => Operand::Indirect(r),
+            3 => Operand::PostInc(r),
+            4 => Operand::PreDec(r),
+            5 => Operand::Disp {
+                r,
+                d: self.word()? as i16 as i32,
+            },
+            6 => self.indexed(Base::A(r))?,
+            _ => match r {
+                0 => Operand::AbsShort(self.word()? as i16 as u32),
+                1 => Operand::AbsLong(self.long()?),
+                2 => {
+                    let base = self.pc;
+                    let d = self.word()? as i16 as u32;
+                    Operand::PcRelative(base.wrapping_add(d))
+                }
+                3 => self.indexed(Base::Pc(self.pc))?,
+                _ => Operand::Immediate(match s {
+                    Size::Long => self.long()?,
+                    Size::Word => self.word()? as u32,
+                    Size::Byte => self.word()? as u32 & 0xff,
+                }),
+            },
+        })
+    }
+
+    fn instr(&mut self) -> Result<DecodedInstr, BusError> {
+        use Instr::*;
+        use Operand as O;
+        let addr = self.pc;
+        let opcode = self.word()?;
+        let ea = opcode & 0o77;
+        let reg = (opcode >> 9 & 7) as u8;
+        let y = (opcode & 7) as u8;
+        let cc = CONDITIONS[(opcode >> 8 & 0xf) as usize];
+        let (mnemonic, size, operands) = match decode_instruction(opcode) {
+            Move(s) => {
+                let src = self.ea(ea, s)?;
+                let dst = self.ea(reg as u16 | (opcode >> 3 & 0o70), s)?;
+                ("move".to_string(), Some(s), vec![src, dst])
+            }
+            Movea(s) => {
+                let src = self.ea(ea, s)?;
+                ("movea".to_string(), Some(s), vec![src, O::AddrReg(reg)])
+            }
+            Moveq => {
+                let imm = O::Quick(opcode as i8 as i32);
+                ("moveq".to_string(), None, vec![imm, O::DataReg(reg)])
+            }
+            Add(s) | Sub(s) => {
+                let name = if opcode >> 12 == 0xd { "add" } else { "sub" };
+                let operand = self.ea(ea, s)?;
+                let operands = if opcode & 0x100 == 0 {
+                    vec![operand, O::DataReg(reg)]
+                } else {
+                    vec![O::DataReg(reg), operand]
+                };
+                (name.to_string(), Some(s), operands)
+            }
+            Adda(s) | Suba(s) => {
+                let name = if opcode >> 12 == 0xd { "adda" } else { "suba" };
+                let src = self.ea(ea, s)?;
+                (name.to_string(), Some(s), vec![src, O::AddrReg(reg)])
+            }
+            Addx(s) | Subx(s) => {
+                let name = if opcode >> 12 == 0xd { "addx" } else { "subx" };
+                let operands = if opcode & 8 == 0 {
+                    vec![O::DataReg(y), O::DataReg(reg)]
+                } else {
+                    vec![O::PreDec(y), O::PreDec(reg)]
+                };
+                (name.to_string(), Some(s), operands)
+            }
+            Cmp(s) => {
+                let src = self.ea(ea, s)?;
+                ("cmp".to_string(), Some(s), vec![src, O::DataReg(reg)])
+            }
+            Cmpa(s) => {
+                let src = self.ea(ea, s)?;
+                ("cmpa".to_string(), Some(s), vec![src, O::AddrReg(reg)])
+            }
+            Cmpi(s) => {
+                let imm = self.ea(0o74, s)?;
+                ("cmpi".to_string(), Some(s), vec![imm, self.ea(ea, s)?])
+            }
+            Cmpm(s) => {
+                let operands = vec![O::PostInc(y), O::PostInc(reg)];
+                ("cmpm".to_string(), Some(s), operands)
+            }
+            Neg(s) => ("neg".to_string(), Some(s), vec![self.ea(ea, s)?]),
+            Negx(s) => ("negx".to_string(), Some(s), vec![self.ea(ea, s)?]),
+            Clr(s) => ("clr".to_string(), Some(s), vec![self.ea(ea, s)?]),
+            Nbcd => {
+                ("nbcd".to_string(), None, vec![self.ea(ea, Size::Byte)?])
+            }
+            Pack | Unpk => {
+                let name = if opcode & 0x40 != 0 { "pack" } else { "unpk" };
+                let adj = O::Immediate(self.word()? as u32);
+                let operands = if opcode & 8 == 0 {
+                    vec![O::DataReg(y), O::DataReg(reg), adj]
+                } else {
+                    vec![O::PreDec(y), O::PreDec(reg), adj]
+                };
+                (name.to_string(), None, operands)
+            }
+            Cas(s) => {
+                let ext = self.word()?;
+                let dc = O::DataReg((ext & 7) as u8);
+                let du = O::DataReg((ext >> 6 & 7) as u8);
+                let operands = vec![dc, du, self.ea(ea, s)?];
+                ("cas".to_string(), Some(s), operands)
+            }
+            Cas2(s) => {
+                let (e1, e2) = (self.word()?, self.word()?);
+                let d = |e: u16, shift: u16| O::DataReg((e >> shift & 7) as u8);
+                let rn = |e: u16| {
+                    let n = (e >> 12 & 7) as u8;
+                    match e & 0x8000 {
+                        0 => O::DataIndirect(n),
+                        _ => O::Indirect(n),
+                    }
+                };
+                let operands = vec![
+                    pair(d(e1, 0), d(e2, 0)),
+                    pair(d(e1, 6), d(e2, 6)),
+                    pair(rn(e1), rn(e2)),
+                ];
+                ("cas2".to_string(), Some(s), operands)
+            }
+            Addq(s) | Subq(s) => {
+                let name = if opcode & 0x100 == 0 { "addq" } else { "subq" };
+                let q = if reg == 0 { 8 } else { reg as i32 };
+                let operands = vec![O::Quick(q), self.ea(ea, s)?];
+                (name.to_string(), Some(s), operands)
+            }
+            Mulu | Muls | Divu | Divs => {
+                let name = match (opcode >> 12, opcode & 0x100 != 0) {
+                    (0xc, false) => "mulu",
+                    (0xc, true) => "muls",
+                    (_, false) => "divu",
+                    (_, true) => "divs",
+                };
+                let src = self.ea(ea, Size::Word)?;
+                let operands = vec![src, O::DataReg(reg)];
+                (name.to_string(), Some(Size::Word), operands)
+            }
+            ShiftReg(s) => {
+                let name = shift_name(opcode >> 3, opcode & 0x100 != 0);
+                let count = if opcode & 0x20 != 0 {
+                    O::DataReg(reg)
+                } else {
+                    O::Quick(if reg == 0 { 8 } else { reg as i32 })
+                };
+                (name, Some(s), vec![count, O::DataReg(y)])
+            }
+            ShiftMem => {
+                let name = shift_name(opcode >> 9, opcode & 0x100 != 0);
+                (name, Some(Size::Word), vec![self.ea(ea, Size::Word)?])
+            }
+            BitField(op) => {
+                let names = [
+                    "bftst", "bfextu", "bfchg", "bfexts", "bfclr", "bfffo",
+                    "bfset", "bfins",
+                ];
+                let ext = self.word()?;
+                let offset = match ext & 0x800 {
+                    0 => BfArg::Imm((ext >> 6 & 0x1f) as u32),
+                    _ => BfArg::Reg((ext >> 6 & 7) as u8),
+                };
+                let width = match (ext & 0x20, ext & 0x1f) {
+                    (0, 0) => BfArg::Imm(32),
+                    (0, w) => BfArg::Imm(w as u32),
+                    _ => BfArg::Reg((ext & 7) as u8),
+                };
+                let field = O::BitField {
+                    ea: Box::new(self.ea(ea, Size::Byte)?),
+                    offset,
+                    width,
+                };
+                let r = O::DataReg((ext >> 12 & 7) as u8);
+                let operands = match op {
+                    BfOp::Extu | BfOp::Exts | BfOp::Ffo => vec![field, r],
+                    BfOp::Ins => vec![r, field],
+                    _ => vec![field],
+                };
+                (names[op as usize].to_string(), None, operands)
+            }
+            // 32-bit displacements are new with the 68020.
+            Bra | Bsr | Bcc
+                if opcode & 0xff == 0xff && self.variant < Variant::M68020 =>
+            {
+                ("dc".to_string(), Some(Size::Word), vec![O::Raw(opcode)])
+            }
+            Bra | Bsr | Bcc => {
+                let name = match opcode >> 8 & 0xf {
+                    0 => "bra".to_string(),
+                    1 => "bsr".to_string(),
+                    _ => format!("b{}", cc),
+                };
+                let base = self.pc;
+                let d = match opcode as u8 {
+                    0 => self.word()? as i16 as u32,
+                    0xff => self.long()?,
+                    d => d as i8 as u32,
+                };
+                (name, None, vec![O::Target(base.wrapping_add(d))])
+            }
+            Scc => {
+                let operands = vec![self.ea(ea, Size::Byte)?];
+                (format!("s{}", cc), None, operands)
+            }
+            Dbcc => {
+                let base = self.pc;
+                let d = self.word()? as i16 as u32;
+                let target = O::Target(base.wrapping_add(d));
+                (format!("db{}", cc), None, vec![O::DataReg(y), target])
+            }
+            Trap(n) => ("trap".to_string(), None, vec![O::Quick(n as i32)]),
+            Trapv => ("trapv".to_string(), None, vec![]),
+            Reset => ("reset".to_string(), None, vec![]),
+            Stop => {
+                let sr = O::Immediate(self.word()? as u32);
+                ("stop".to_string(), None, vec![sr])
+            }
+            Jsr => ("jsr".to_string(), None, vec![self.ea(ea, Size::Long)?]),
+            Lea => {
+                let src = self.ea(ea, Size::Long)?;
+                ("lea".to_string(), None, vec![src, O::AddrReg(reg)])
+            }
+            Chk => {
+                let s = Size::Word;
+                let src = self.ea(ea, s)?;
+                ("chk".to_string(), Some(s), vec![src, O::DataReg(reg)])
+            }
+            Rts => ("rts".to_string(), None, vec![]),
+            Rte => ("rte".to_string(), None, vec![]),
+            MoveFromSr => {
+                let dst = self.ea(ea, Size::Word)?;
+                ("move".to_string(), None, vec![O::Sr, dst])
+            }
+            MoveFromCcr => {
+                let dst = self.ea(ea, Size::Word)?;
+                ("move".to_string(), None, vec![O::Ccr, dst])
+            }
+            MoveToSr => {
+                let src = self.ea(ea, Size::Word)?;
+                ("move".to_string(), None, vec![src, O::Sr])
+            }
+            OriToSr | AndiToSr | EoriToSr => {
+                let name = match opcode >> 9 & 7 {
+                    0 => "ori",
+                    1 => "andi",
+                    _ => "eori",
+                };
+                let imm = O::Immediate(self.word()? as u32);
+                (name.to_string(), None, vec![imm, O::Sr])
+            }
+            Tst(s) => ("tst".to_string(), Some(s), vec![self.ea(ea, s)?]),
+            Tas => ("tas".to_string(), None, vec![self.ea(ea, Size::Byte)?]),
+            Movec => {
+                let ext = self.word()?;
+                let n = (ext >> 12 & 7) as u8;
+                let r = match ext & 0x8000 {
+                    0 => O::DataReg(n),
+                    _ => O::AddrReg(n),
+                };
+                let ctrl = O::Control(ext & 0xfff);
+                let operands = match opcode & 1 {
+                    0 => vec![ctrl, r],
+                    _ => vec![r, ctrl],
+                };
+                ("movec".to_string(), None, operands)
+            }
+            Move16 => {
+                let operands = if opcode & 0o70 == 0o40 {
+                    let ay = (self.word()? >> 12 & 7) as u8;
+                    vec![O::PostInc(y), O::PostInc(ay)]
+                } else {
+                    let abs = O::AbsLong(self.long()?);
+                    let ay = match opcode & 0o20 {
+                        0 => O::PostInc(y),
+                        _ => O::Indirect(y),
+                    };
+                    match opcode & 0o10 {
+                        0 => vec![ay, abs],
+                        _ => vec![abs, ay],
+                    }
+                };
+                ("move16".to_string(), None, operands)
+            }
+            Illegal if opcode == 0x4afc => {
+                ("illegal".to_string(), None, vec![])
+            }
+            LineA | LineF | Illegal => {
+                ("dc".to_string(), Some(Size::Word), vec![O::Raw(opcode)])
+            }
+        };
+        Ok(DecodedInstr {
+            addr,
+            mnemonic,
+            size,
+            operands,
+        })
+    }
+}
+
+fn shift_name(kind: u16, left: bool) -> String {
+    let name = ["as", "ls", "rox", "ro"][(kind & 3) as usize];
+    format!("{}{}", name, if left { 'l' } else { 'r' })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{poke, Ram};
+
+    fn decode(words: &[u16]) -> (DecodedInstr, String) {
+        let mut ram = Ram::new(0, 0x1000);
+        for (i, &w) in words.iter().enumerate() {
+            let addr = 0x100 + 2 * i as u32;
+            poke(&mut ram, addr, Size::Word, w as u32).unwrap();
+        }
+        let instr = decode_at(&mut ram, 0x100, Variant::M68020).unwrap();
+        let text = instr.render(None);
+        (instr, text)
+    }
+
+    #[test]
+    fn indexed_operand() {
+        let (instr, text) = decode(&[0x3030, 0x1004]);
+        assert_eq!(text, "move.w $4(a0,d1.w),d0");
+        assert_eq!(instr.mnemonic, "move");
+        assert_eq!(instr.size, Some(Size::Word));
+        let idx = IndexReg {
+            addr: false,
+            r: 1,
+            long: false,
+            scale: 0,
+        };
+        let src = Operand::Indexed {
+            base: Base::A(0),
+            d: 4,
+            idx: Some(idx),
+        };
+        assert_eq!(instr.operands, [src, Operand::DataReg(0)]);
+    }
+
+    #[test]
+    fn status_register_operand() {
+        let (instr, text) = decode(&[0x007c, 0x0700]);
+        assert_eq!(text, "ori #$700,sr");
+        assert_eq!(instr.operands, [Operand::Immediate(0x700), Operand::Sr]);
+    }
+
+    #[test]
+    fn bit_field_operand() {
+        let (instr, text) = decode(&[0xe9d0, 0x1108]);
+        assert_eq!(text, "bfextu (a0){4:8},d1");
+        let field = Operand::BitField {
+            ea: Box::new(Operand::Indirect(0)),
+            offset: BfArg::Imm(4),
+            width: BfArg::Imm(8),
+        };
+        assert_eq!(instr.operands, [field, Operand::DataReg(1)]);
+    }
+
+    #[test]
+    fn long_branches_need_a_68020() {
+        let mut ram = Ram::new(0, 0x1000);
+        for (i, w) in [0x60ff, 0x0000, 0x0100].into_iter().enumerate() {
+            poke(&mut ram, 0x100 + 2 * i as u32, Size::Word, w).unwrap();
+        }
+        let mut text = |variant| disassemble(&mut ram, 0x100, None, variant);
+        assert_eq!(text(Variant::M68020).unwrap(), ("bra $202".into(), 6));
+        assert_eq!(text(Variant::M68000).unwrap(), ("dc.w $60ff".into(), 2));
+    }
+
+    #[test]
+    fn memory_indirect_operand() {
+        let (_, text) = decode(&[0x43f0, 0x0122, 0x0010, 0x0008]);
+        assert_eq!(text, "lea ([$10,a0,d0.w],$8),a1");
+        let (_, text) = decode(&[0x43f0, 0x01e1, 0x0010]);
+        assert_eq!(text, "lea ([$10]),a1");
+    }
+
+    #[test]
+    fn branch_target_by_name() {
+        // bra.s *+$10
+        let mut ram = Ram::new(0, 0x1000);
+        poke(&mut ram, 0x100, Size::Word, 0x600e).unwrap();
+        let symbols = HashMap::from([(0x110, "loop".to_string())]);
+        let text = disassemble(&mut ram, 0x100, Some(&symbols), Variant::M68000)
+            .unwrap();
+        assert_eq!(text, ("bra loop".into(), 2));
+    }
+
+    #[test]
+    fn addx_operands() {
+        let (instr, text) = decode(&[0xd581]);
+        assert_eq!(text, "addx.l d1,d2");
+        assert_eq!(instr.operands, [Operand::DataReg(1), Operand::DataReg(2)]);
+        let (instr, text) = decode(&[0x9549]);
+        assert_eq!(text, "subx.w -(a1),-(a2)");
+        assert_eq!(instr.operands, [Operand::PreDec(1), Operand::PreDec(2)]);
+    }
+}
@@ -0,0 +1,327 @@
+use crate::{EffAddr, Index, Reg, Size};
+
+// Builds opcodes and their extension words from operands, for assembling
+// programs without looking up bit layouts.
+
+// Operands no instruction word can express.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodeError {
+    // ADDQ takes 1 to 8.
+    QuickOutOfRange(u8),
+    // Address registers are only ever accessed as words or longs.
+    ByteToAddrReg,
+    // An immediate wider than the size of the operation.
+    ImmediateOutOfRange(u32),
+    // An addressing mode the instruction does not take, e.g. a PC relative
+    // destination, or a postindexed one without an index.
+    BadMode(EffAddr),
+    // Only data and address registers can index.
+    BadIndex(Reg),
+}
+
+pub fn encode_moveq(reg: u8, imm: i8) -> u16 {
+    0x7000 | (reg as u16 & 7) << 9 | imm as u8 as u16
+}
+
+// ADDQ #imm,<ea>, `imm` being 1 to 8.
+pub fn encode_addq(
+    s: Size,
+    imm: u8,
+    ea: EffAddr,
+) -> Result<Vec<u16>, EncodeError> {
+    if !(1..=8).contains(&imm) {
+        return Err(EncodeError::QuickOutOfRange(imm));
+    }
+    match ea {
+        EffAddr::AddrReg { .. } if s == Size::Byte => {
+            return Err(EncodeError::ByteToAddrReg);
+        }
+        EffAddr::PCIndDisp { .. }
+        | EffAddr::PCIndIdx { .. }
+        | EffAddr::PCIndPostIdx { .. }
+        | EffAddr::PCIndPreIdx { .. }
+        | EffAddr::Immediate { .. } => return Err(EncodeError::BadMode(ea)),
+        _ => (),
+    }
+    let (field, ext) = encode_ea(ea, s)?;
+    let opcode = 0x5000 | (imm as u16 & 7) << 9 | size_bits(s) << 6 | field;
+    Ok([vec![opcode], ext].concat())
+}
+
+// Bcc with condition code `cc`, 0 and 1 giving BRA and BSR. `disp` is
+// relative to the word following the opcode. The shortest form is chosen,
+// the 32-bit one being for the 68020 and later.
+pub fn encode_bcc(cc: u8, disp: i32) -> Vec<u16> {
+    let opcode = 0x6000 | (cc as u16 & 0xf) << 8;
+    match disp {
+        // 0 and -1 announce the word and long forms.
+        -128..=127 if disp != 0 && disp != -1 => {
+            vec![opcode | disp as u8 as u16]
+        }
+        -0x8000..=0x7fff => vec![opcode, disp as u16],
+        _ => vec![opcode | 0xff, (disp >> 16) as u16, disp as u16],
+    }
+}
+
+fn size_bits(s: Size) -> u16 {
+    match s {
+        Size::Byte => 0,
+        Size::Word => 1,
+        Size::Long => 2,
+    }
+}
+
+// The 6-bit mode/register field of `ea` and its extension words, `s` being
+// the size of immediates. Indexed modes use the brief extension word when
+// they can, the full format of the 68020 otherwise.
+pub fn encode_ea(ea: EffAddr, s: Size) -> Result<(u16, Vec<u16>), EncodeError> {
+    // Postindexing needs an index, the full format has no encoding for
+    // going through memory with neither.
+    if let EffAddr::AddrIndPostIdx { idx: None, .. }
+    | EffAddr::PCIndPostIdx { idx: None, .. } = ea
+    {
+        return Err(EncodeError::BadMode(ea));
+    }
+    Ok(match ea {
+        EffAddr::DataReg { r } => (r as u16, vec![]),
+        EffAddr::AddrReg { r } => (0o10 | r as u16, vec![]),
+        EffAddr::Addr { r } => (0o20 | r as u16, vec![]),
+        EffAddr::PostInc { r, .. } => (0o30 | r as u16, vec![]),
+        EffAddr::PreDec { r, .. } => (0o40 | r as u16, vec![]),
+        EffAddr::AddrDisp { r, d } => (0o50 | r as u16, vec![d as u16]),
+        EffAddr::AddrIdx { r, idx, d } => {
+            let ext = indexed_ext(r.is_none(), idx, d, None)?;
+            (0o60 | r.unwrap_or(0) as u16, ext)
+        }
+        EffAddr::AddrIndPreIdx { r, d, idx, od } => {
+            let ext = indexed_ext(r.is_none(), idx, d, Some((od, true)))?;
+            (0o60 | r.unwrap_or(0) as u16, ext)
+        }
+        EffAddr::AddrIndPostIdx { r, d, idx, od } => {
+            let ext = indexed_ext(r.is_none(), idx, d, Some((od, false)))?;
+            (0o60 | r.unwrap_or(0) as u16, ext)
+        }
+        EffAddr::AbsShort { addr } => (0o70, vec![addr as u16]),
+        EffAddr::AbsLong { hi, lo } => (0o71, vec![hi, lo]),
+        EffAddr::PCIndDisp { d, .. } => match i16::try_from(d) {
+            Ok(d) => (0o72, vec![d as u16]),
+            Err(_) => (0o73, indexed_ext(false, None, d, None)?),
+        },
+        EffAddr::PCIndIdx { base, d, idx } => {
+            (0o73, indexed_ext(base.is_none(), idx, d, None)?)
+        }
+        EffAddr::PCIndPreIdx { base, d, idx, od } => {
+            let ext = indexed_ext(base.is_none(), idx, d, Some((od, true)))?;
+            (0o73, ext)
+        }
+        EffAddr::PCIndPostIdx { base, d, idx, od } => {
+            let ext = indexed_ext(base.is_none(), idx, d, Some((od, false)))?;
+            (0o73, ext)
+        }
+        EffAddr::Immediate { x } => {
+            if x & !s.mask() != 0 {
+                return Err(EncodeError::ImmediateOutOfRange(x));
+            }
+            match s {
+                Size::Long => (0o74, vec![(x >> 16) as u16, x as u16]),
+                _ => (0o74, vec![x as u16]),
+            }
+        }
+    })
+}
+
+// Extension words of the indexed modes. `indirect` gives the outer
+// displacement and whether the index is added before going through memory.
+fn indexed_ext(
+    suppress_base: bool,
+    idx: Option<Index>,
+    d: i32,
+    indirect: Option<(i32, bool)>,
+) -> Result<Vec<u16>, EncodeError> {
+    let idx_bits = match idx {
+        Some(idx) => index_bits(idx)?,
+        None => 0x40,
+    };
+    let brief = (-128..=127).contains(&d) && indirect.is_none();
+    if brief && !suppress_base && idx.is_some() {
+        return Ok(vec![idx_bits | d as u8 as u16]);
+    }
+    let mut ext = vec![idx_bits | 0x100 | (suppress_base as u16) << 7];
+    ext[0] |= displacement(d, &mut ext) << 4;
+    if let Some((od, preindex)) = indirect {
+        let post = if preindex { 0 } else { 4 };
+        ext[0] |= post | displacement(od, &mut ext);
+    }
+    Ok(ext)
+}
+
+// Appends the base or outer displacement `d` to `ext`, returning its size
+// as the full format gives it: 1 for none, 2 for a word and 3 for a long.
+fn displacement(d: i32, ext: &mut Vec<u16>) -> u16 {
+    match d {
+        0 => 1,
+        -0x8000..=0x7fff => {
+            ext.push(d as u16);
+            2
+        }
+        _ => {
+            ext.extend([(d >> 16) as u16, d as u16]);
+            3
+        }
+    }
+}
+
+// Register, size and scale of an index, common to both formats.
+fn index_bits(idx: Index) -> Result<u16, EncodeError> {
+    let r = match idx.r {
+        Reg::D(n) => n as u16,
+        Reg::A(n) => 0x8 | n as u16,
+        r => return Err(EncodeError::BadIndex(r)),
+    };
+    let scale = (idx.scale as u16 & 3) << 9;
+    Ok(r << 12 | (idx.long as u16) << 11 | scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{poke, Ram};
+    use crate::{Variant, M68K};
+
+    // Decodes the mode/register field `field` off `ext`, placed after an
+    // opcode at $100, and checks that all of it was read.
+    fn decode(field: u16, ext: &[u16], s: Size) -> EffAddr {
+        let mut ram = Ram::new(0, 0x1000);
+        for (i, &w) in ext.iter().enumerate() {
+            poke(&mut ram, 0x102 + 2 * i as u32, Size::Word, w as u32).unwrap();
+        }
+        let mut cpu = M68K::with_state(0x102, 0x2700, 0x800);
+        cpu.variant = Variant::M68020;
+        cpu.ir_pc = 0x100;
+        let ea = cpu.decode_effaddr(&mut ram, field, s).unwrap();
+        assert_eq!(cpu.pc, 0x102 + 2 * ext.len() as u32, "{:?}", ea);
+        ea
+    }
+
+    #[test]
+    fn round_trip() {
+        let d2 = Index {
+            r: Reg::D(2),
+            long: false,
+            scale: 0,
+        };
+        let a3 = Index {
+            r: Reg::A(3),
+            long: true,
+            scale: 2,
+        };
+        let base = Some(0x102);
+        let (s, byte) = (Size::Long, Size::Byte);
+        for (ea, s) in [
+            (EffAddr::DataReg { r: 1 }, s),
+            (EffAddr::AddrReg { r: 7 }, s),
+            (EffAddr::PostInc { r: 2, s }, s),
+            (EffAddr::AddrDisp { r: 4, d: -2 }, s),
+            (
+                EffAddr::AddrIdx {
+                    r: Some(5),
+                    idx: Some(a3),
+                    d: -128,
+                },
+                s,
+            ),
+            (
+                EffAddr::AddrIdx {
+                    r: Some(5),
+                    idx: Some(d2),
+                    d: 0x80,
+                },
+                s,
+            ),
+            (
+                EffAddr::AddrIdx {
+                    r: None,
+                    idx: None,
+                    d: 0x12345,
+                },
+                s,
+            ),
+            (
+                EffAddr::PCIndDisp {
+                    base: 0x102,
+                    d: 0x7fff,
+                },
+                s,
+            ),
+            (
+                EffAddr::PCIndIdx {
+                    base,
+                    idx: Some(d2),
+                    d: 4,
+                },
+                s,
+            ),
+            (
+                EffAddr::PCIndIdx {
+                    base: None,
+                    idx: Some(a3),
+                    d: 0,
+                },
+                s,
+            ),
+            (
+                EffAddr::AddrIndPreIdx {
+                    r: Some(0),
+                    d: 0,
+                    idx: None,
+                    od: -0x10000,
+                },
+                s,
+            ),
+            (
+                EffAddr::PCIndPostIdx {
+                    base,
+                    d: 8,
+                    idx: Some(a3),
+                    od: 6,
+                },
+                s,
+            ),
+            (
+                EffAddr::AbsLong {
+                    hi: 0x12,
+                    lo: 0x3456,
+                },
+                s,
+            ),
+            (EffAddr::Immediate { x: 0xdeadbeef }, s),
+            (EffAddr::Immediate { x: 0xfe }, byte),
+        ] {
+            let (field, ext) = encode_ea(ea, s).unwrap();
+            assert_eq!(decode(field, &ext, s), ea);
+        }
+    }
+
+    #[test]
+    fn bad_operands() {
+        let a0 = EffAddr::AddrReg { r: 0 };
+        assert_eq!(
+            encode_addq(Size::Byte, 1, a0),
+            Err(EncodeError::ByteToAddrReg)
+        );
+        assert_eq!(
+            encode_addq(Size::Word, 9, a0),
+            Err(EncodeError::QuickOutOfRange(9))
+        );
+        let imm = EffAddr::Immediate { x: 0x100 };
+        assert_eq!(
+            encode_addq(Size::Word, 1, imm),
+            Err(EncodeError::BadMode(imm))
+        );
+        assert_eq!(
+            encode_ea(imm, Size::Byte),
+            Err(EncodeError::ImmediateOutOfRange(0x100))
+        );
+        assert_eq!(encode_addq(Size::Word, 8, a0), Ok(vec![0x5048]));
+    }
+}
@@ -0,0 +1,22 @@
+; hello.bin, hand-assembled: prints a line on DUART channel A, then halts
+; the CPU by taking an exception with an odd stack pointer.
+
+DUART   equ     $ff0000
+THRA    equ     DUART+7
+
+        org     0
+        dc.l    $8000                   ; 0000  0000 8000
+        dc.l    start                   ; 0004  0000 0010
+        dc.l    0, 0                    ; 0008  0000 0000 0000 0000
+
+start:  movea.l #msg,a0                 ; 0010  207c 0000 0030
+        movea.l #THRA,a1                ; 0016  227c 00ff 0007
+loop:   move.b  (a0)+,d0                ; 001c  1018
+        beq.s   done                    ; 001e  6704
+        move.b  d0,(a1)                 ; 0020  1280
+        bra.s   loop                    ; 0022  60f8
+done:   movea.l #1,a7                   ; 0024  2e7c 0000 0001
+        illegal                         ; 002a  4afc
+        dc.w    0, 0                    ; 002c  0000 0000
+
+msg:    dc.b    "hello, world",10,0     ; 0030
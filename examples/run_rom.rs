@@ -0,0 +1,37 @@
+use genesis::system::System;
+use genesis::{boot, StepOutcome};
+use std::io::Write;
+
+// Boots the ROM image given on the command line, e.g. examples/hello.bin,
+// on a board with RAM and a console DUART, and runs it until the CPU halts,
+// echoing DUART channel A to stdout.
+fn main() {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("usage: run_rom <rom image>");
+        std::process::exit(2)
+    };
+    let rom = std::fs::read(&path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1)
+    });
+    let mut sys = System::new(&rom);
+    let mut cpu = boot(&mut sys);
+    let mut out = std::io::stdout();
+    loop {
+        let outcome = cpu.step(&mut sys);
+        if !sys.duart.tx[0].is_empty() {
+            out.write_all(&sys.duart.tx[0]).unwrap();
+            out.flush().unwrap();
+            sys.duart.tx[0].clear();
+        }
+        match outcome {
+            StepOutcome::DoubleFault => break,
+            StepOutcome::DecodeError(e) => {
+                eprintln!("{:?}", e);
+                break;
+            }
+            _ => {}
+        }
+    }
+    eprintln!("halted at pc {:08x}", cpu.snapshot().pc);
+}
@@ -0,0 +1,140 @@
+use crate::system::System;
+use crate::{boot, M68K};
+
+// Small programs run for a fixed number of steps, whose register traces are
+// checked against the ones kept in golden/. Each starts with its reset
+// vectors, like the ROMs System takes.
+#[rustfmt::skip]
+const ARITH: [u16; 19] = [
+    0x0000, 0x8000, 0x0000, 0x0008,
+    0x203c, 0x0001, 0x2345, // $08: move.l #$12345,d0
+    0x223c, 0xffff, 0xfff0, //      move.l #-16,d1
+    0xd081,                 //      add.l d1,d0
+    0x5380,                 //      subq.l #1,d0
+    0x4481,                 //      neg.l d1
+    0xc2c0,                 //      mulu.w d0,d1
+    0xe389,                 //      lsl.l #1,d1
+    0x9280,                 //      sub.l d0,d1
+    0xd381,                 //      addx.l d1,d1
+    0x80c1,                 //      divu.w d1,d0
+    0x4a80,                 //      tst.l d0
+];
+
+#[rustfmt::skip]
+const BRANCH: [u16; 17] = [
+    0x0000, 0x8000, 0x0000, 0x0008,
+    0x203c, 0x0000, 0x0003, // $08: move.l #3,d0
+    0x5281,                 // $0e: addq.l #1,d1
+    0x51c8, 0xfffc,         //      dbf d0,$0e
+    0x0c81, 0x0000, 0x0004, //      cmpi.l #4,d1
+    0x6602,                 //      bne.s $1e
+    0x6102,                 //      bsr.s $20
+    0x60fe,                 // $1e: bra.s $1e
+    0x4e75,                 // $20: rts
+];
+
+#[rustfmt::skip]
+const MEMORY: [u16; 18] = [
+    0x0000, 0x8000, 0x0000, 0x0008,
+    0x207c, 0x0000, 0x1000, // $08: movea.l #$1000,a0
+    0x20fc, 0x1234, 0x5678, //      move.l #$12345678,(a0)+
+    0x30fc, 0xabcd,         //      move.w #$abcd,(a0)+
+    0x2020,                 //      move.l -(a0),d0
+    0x3210,                 //      move.w (a0),d1
+    0x1428, 0x0001,         //      move.b 1(a0),d2
+    0x2f00,                 //      move.l d0,-(a7)
+    0x261f,                 //      move.l (a7)+,d3
+];
+
+// MOVE.W into D0 from each of the twelve source modes, reading the table at
+// $3c.
+#[rustfmt::skip]
+const MODES: [u16; 38] = [
+    0x0000, 0x8000, 0x0000, 0x0008,
+    0x207c, 0x0000, 0x003c, // $08: movea.l #$3c,a0
+    0x223c, 0x0000, 0x0002, //      move.l #2,d1
+    0x3001,                 //      move.w d1,d0
+    0x3008,                 //      move.w a0,d0
+    0x3018,                 //      move.w (a0)+,d0
+    0x3010,                 //      move.w (a0),d0
+    0x3020,                 //      move.w -(a0),d0
+    0x3028, 0x0004,         //      move.w 4(a0),d0
+    0x3030, 0x1004,         //      move.w 4(a0,d1.w),d0
+    0x3038, 0x0044,         //      move.w ($44).w,d0
+    0x3039, 0x0000, 0x0046, //      move.w ($46).l,d0
+    0x303a, 0x0016,         // $30: move.w $48(pc),d0
+    0x303b, 0x1012,         //      move.w $48(pc,d1.w),d0
+    0x303c, 0x9999,         //      move.w #$9999,d0
+    0x1111, 0x2222, 0x3333, 0x4444, // $3c
+    0x5555, 0x6666, 0x7777, 0x8888,
+];
+
+// Name, program, steps to run and expected trace.
+const PROGRAMS: [(&str, &[u16], usize, &str); 4] = [
+    ("arith", &ARITH, 11, include_str!("golden/arith.trace")),
+    ("branch", &BRANCH, 14, include_str!("golden/branch.trace")),
+    ("memory", &MEMORY, 8, include_str!("golden/memory.trace")),
+    ("modes", &MODES, 14, include_str!("golden/modes.trace")),
+];
+
+fn trace_line(cpu: &M68K) -> String {
+    let regs = cpu.data_r.iter().chain(&cpu.addr_r);
+    let regs: Vec<String> = regs.map(|r| format!("{:08x}", r)).collect();
+    format!("{:08x} {:04x} {}", cpu.pc, cpu.sr, regs.join(" "))
+}
+
+// PC, SR, D0-D7 and A0-A7 after each of `steps` steps of `program`.
+pub fn trace(program: &[u16], steps: usize) -> Vec<String> {
+    let rom: Vec<u8> = program.iter().flat_map(|w| w.to_be_bytes()).collect();
+    let mut sys = System::new(&rom);
+    let mut cpu = boot(&mut sys);
+    (0..steps)
+        .map(|_| {
+            cpu.step(&mut sys);
+            trace_line(&cpu)
+        })
+        .collect()
+}
+
+// Runs every program, reporting the first step that differs from its
+// golden trace.
+pub fn check() -> Result<(), String> {
+    for (name, program, steps, golden) in PROGRAMS {
+        let got = trace(program, steps);
+        let want: Vec<&str> = golden.lines().collect();
+        for i in 0..got.len().max(want.len()) {
+            let (g, w) = (got.get(i).map(|l| l.as_str()), want.get(i));
+            if g != w.copied() {
+                return Err(format!(
+                    "{}: step {} differs\n  want: {}\n  got:  {}",
+                    name,
+                    i + 1,
+                    w.unwrap_or(&"<end>"),
+                    g.unwrap_or("<end>")
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Rewrites the golden traces from what the emulator does now. They are
+// included at build time, so checks only see them after a rebuild.
+pub fn bless(dir: &str) -> std::io::Result<()> {
+    for (name, program, steps, _) in PROGRAMS {
+        let mut text = trace(program, steps).join("\n");
+        text.push('\n');
+        std::fs::write(format!("{}/{}.trace", dir, name), text)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn golden_traces() {
+        if let Err(e) = super::check() {
+            panic!("{}", e);
+        }
+    }
+}
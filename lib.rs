@@ -0,0 +1,4087 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+pub mod bus;
+mod decode;
+pub mod disasm;
+pub mod duart;
+pub mod encode;
+pub mod golden;
+pub mod srec;
+pub mod system;
+
+use bus::{Bus, BusError, BusWidth, FunctionCode, MemAccess};
+pub use decode::{
+    decode_coverage, opcode_class, CoverageReport, Instr, InstrClass,
+};
+use disasm::DecodedInstr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reg {
+    D(usize),
+    A(usize),
+    PC,
+    CCR,
+    // Written through set_sr, so that S switches the stack pointers.
+    SR,
+    // Control registers reached through MOVEC.
+    USP,
+    // Master and interrupt stack pointers, 68020 and later.
+    MSP,
+    ISP,
+    VBR,
+    SFC,
+    DFC,
+    CACR,
+    CAAR,
+    InTmp(usize),
+    In0,
+    In1,
+    // Result of the last memory read, zero-extended from its size. Users
+    // wanting it signed go through Ext.
+    IOBuffer,
+    Immediate(i32),
+}
+
+const NB_INTERNAL_REGS: usize = 8;
+
+// Leaving STOP for an exception costs one more idle bus cycle. The
+// M68000 User's Manual gives STOP as 4(0/0), four clocks with no bus
+// cycle, and the interrupt lines are only sampled between bus cycles.
+const STOP_WAKEUP_CYCLES: u64 = 4;
+// How long RESET holds the line, on top of its opcode fetch.
+const RESET_CYCLES: u64 = 124;
+// The longest 68020 instructions, MOVE between two memory indirect
+// addresses with 32-bit displacements, have ten extension words.
+const MAX_EXT_WORDS: u32 = 10;
+
+pub struct M68K {
+    data_r: [u32; 8],
+
+    addr_r: [u32; 8],
+
+    pc: u32,
+    sr: u16,
+    // Stack pointers of the inactive modes, A7 holds the active one. From
+    // the 68020 on, the supervisor uses msp when SR's M bit is set and ssp,
+    // the interrupt stack pointer, otherwise.
+    usp: u32,
+    ssp: u32,
+    msp: u32,
+    // Base of the exception vector table, 68010 and later.
+    vbr: u32,
+    // Function codes used by MOVES, 68010 and later.
+    sfc: u8,
+    dfc: u8,
+    // Cache control and address registers, 68020 and later. There is no
+    // cache, they only hold what is written to them.
+    cacr: u32,
+    caar: u32,
+
+    intern_r: [u32; NB_INTERNAL_REGS + 3],
+
+    instrs: VecDeque<MicroI>,
+    // The word after the instruction being executed, read ahead while it
+    // runs, with its address and space. Fetching it again gets this copy,
+    // even if the instruction wrote over it.
+    prefetch: Option<(u32, FunctionCode, u16)>,
+
+    cycles: u64,
+    bus_width: BusWidth,
+    align_policy: AlignPolicy,
+    power_on_fill: PowerOnFill,
+    // Extension words an instruction may have, and why decoding the last
+    // one stopped short of running it.
+    max_ext_words: u32,
+    decode_error: Option<DecodeError>,
+    mem_trace: Option<Box<dyn FnMut(MemAccess)>>,
+    // Called with each instruction that completes, traps included.
+    retire_trace: Option<RetireHook>,
+    // Called with the address and value of each opcode and extension word
+    // fetched.
+    fetch_trace: Option<FetchHook>,
+    // Called with the number of each exception vector the CPU writes to,
+    // in the table VBR points at, and the first one written in this step.
+    vector_watch: Option<VectorHook>,
+    vector_written: Option<u8>,
+
+    state: State,
+    variant: Variant,
+
+    // Opcode and address of the instruction being executed and the last
+    // memory access, for exception frames.
+    ir: u16,
+    ir_pc: u32,
+    fault: AccessFault,
+    // Words fetched for the instruction, opcode first.
+    ir_words: Vec<u16>,
+
+    // Interrupt request lines asserted, bit n for level n.
+    irq_lines: u8,
+
+    // Host code run by TRAP #n instead of taking the exception.
+    trap_handlers: [Option<TrapHandler>; 16],
+
+    timers: Vec<Timer>,
+
+    // Vectors to stop stepping at, and the first one raised in this step.
+    // Its exception is held, with the level of the interrupt it is for if
+    // any, and taken by the next step.
+    exception_breaks: Vec<u8>,
+    exception_hit: Option<u8>,
+    held: Option<(u8, Option<u8>)>,
+
+    // Undo records of the last `history_len` steps, the latest at the back.
+    history: VecDeque<Undo>,
+    history_len: usize,
+
+    stats: Option<ExecStats>,
+}
+
+// A device interrupting every `period` cycles. Its request is held until
+// the CPU acknowledges it.
+struct Timer {
+    period: u64,
+    deadline: u64,
+    level: u8,
+    vector: u8,
+    raised: bool,
+}
+
+type TrapHandler = Box<dyn FnMut(&mut M68K, &mut dyn Bus)>;
+type RetireHook = Box<dyn FnMut(&DecodedInstr)>;
+type FetchHook = Box<dyn FnMut(u32, u16)>;
+type VectorHook = Box<dyn FnMut(u8)>;
+
+// The programmer-visible registers at some point, to compare before and
+// after running code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuState {
+    pub data_r: [u32; 8],
+    pub addr_r: [u32; 8],
+    pub pc: u32,
+    pub sr: u16,
+}
+
+// Instructions executed and the cycles they took, exceptions they raised
+// included, by class.
+#[derive(Debug, Default)]
+pub struct ExecStats {
+    pub per_class: HashMap<InstrClass, ClassStats>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClassStats {
+    pub count: u64,
+    pub cycles: u64,
+}
+
+// The state before a step, with the previous contents of what it wrote
+// in the order of the writes.
+struct Undo {
+    cpu: CpuState,
+    usp: u32,
+    ssp: u32,
+    msp: u32,
+    vbr: u32,
+    sfc: u8,
+    dfc: u8,
+    cacr: u32,
+    caar: u32,
+    cycles: u64,
+    state: State,
+    irq_lines: u8,
+    held: Option<(u8, Option<u8>)>,
+    // Deadline and request of each timer.
+    timers: Vec<(u64, bool)>,
+    writes: Vec<(u32, Size, u32)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+// A register that differs, with the value in `self` then the one in
+// `other`.
+pub enum RegChange {
+    D(usize, u32, u32),
+    A(usize, u32, u32),
+    PC(u32, u32),
+    Flag(Flag, bool, bool),
+    // Any of the system byte of SR.
+    System(u8, u8),
+}
+
+impl CpuState {
+    // What differs between `self` and `other`, registers first.
+    pub fn diff(&self, other: &CpuState) -> Vec<RegChange> {
+        use Flag::*;
+        let mut changes = Vec::new();
+        for r in 0..8 {
+            let (old, new) = (self.data_r[r], other.data_r[r]);
+            if old != new {
+                changes.push(RegChange::D(r, old, new));
+            }
+        }
+        for r in 0..8 {
+            let (old, new) = (self.addr_r[r], other.addr_r[r]);
+            if old != new {
+                changes.push(RegChange::A(r, old, new));
+            }
+        }
+        if self.pc != other.pc {
+            changes.push(RegChange::PC(self.pc, other.pc));
+        }
+        let sr = self.sr ^ other.sr;
+        for f in [Extend, Negative, Zero, Overflow, Carry] {
+            if sr & f.mask() != 0 {
+                let old = self.sr & f.mask() != 0;
+                changes.push(RegChange::Flag(f, old, !old));
+            }
+        }
+        if sr & 0xff00 != 0 {
+            let (old, new) = ((self.sr >> 8) as u8, (other.sr >> 8) as u8);
+            changes.push(RegChange::System(old, new));
+        }
+        changes
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MicroI {
+    Zero(Reg),
+    Set(Reg, u32),
+    Mov(Reg, Reg),
+    Alu {
+        op: AluOp,
+        dst: Reg,
+        src: Reg,
+        size: Size,
+        set_flags: bool,
+    },
+    Scale(Reg, u8),
+    // Logical shift right of the whole register, the flags left alone.
+    Unscale(Reg, u8),
+    // Sign-extends the low bits of a register to 32 bits.
+    Ext(Reg, Size),
+    Mul { signed: bool, dst: Reg, src: Reg },
+    // Shifts or rotates the low `size` bits of `dst` by the count in `count`,
+    // taken modulo 64.
+    Shift {
+        op: ShiftOp,
+        dst: Reg,
+        count: Reg,
+        size: Size,
+    },
+    Div { signed: bool, dst: Reg, src: Reg },
+    RequestMem(Reg, Size),
+    // Same from program space, for immediates and PC relative operands.
+    RequestProgram(Reg, Size),
+    // Stores the value of the second register at the first one's address.
+    WriteMem(Reg, Reg, Size),
+    // Takes the exception `vector` if condition code `cc` holds.
+    TrapIf(u8, u8),
+    // Jumps to the address in the register if condition code `cc` holds.
+    BranchIf(u8, Reg),
+    // Sets the low byte of the register to all ones if `cc` holds, zero
+    // otherwise.
+    SetIf(u8, Reg),
+    // DBcc: unless `cc` holds, decrements the low word of the counter and
+    // jumps to the target if it did not reach -1.
+    DecBranch(u8, Reg, Reg),
+    // Enters the stopped state.
+    StopCpu,
+    // Asserts the RESET line, the CPU itself is left alone.
+    ResetDevices,
+    // Drops the next `n` micro-ops if condition code `cc` holds.
+    SkipIf(u8, usize),
+    // Asserts or releases the bus lock of read-modify-write cycles.
+    Lock(bool),
+    // The `width` bits starting `pos` bits from the top of data register
+    // `data`, or of the bytes gathered in InTmp(3) onwards when there is
+    // none. `offset` is the one given to the instruction, for BFFFO.
+    BitField {
+        op: BfOp,
+        data: Option<usize>,
+        pos: u32,
+        width: u32,
+        offset: u32,
+        reg: usize,
+    },
+    // RTE on the 68010 and later: pops the exception frame whose format word
+    // is in the register off the supervisor stack, or takes a format error.
+    PopFrame(Reg),
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Reg::D(n) => write!(f, "d{}", n),
+            Reg::A(n) => write!(f, "a{}", n),
+            Reg::InTmp(n) => write!(f, "tmp{}", n),
+            Reg::Immediate(x) => write!(f, "#{}", x),
+            r => write!(f, "{}", format!("{:?}", r).to_lowercase()),
+        }
+    }
+}
+
+// Operands in the order of the fields, the destination first, so that
+// "Add.l in0,in1" adds in1 to in0. Conditions go by their mnemonic.
+impl fmt::Display for MicroI {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use disasm::suffix;
+        use MicroI::*;
+        let cc = |cc: &u8| disasm::CONDITIONS[*cc as usize & 0xf];
+        let sign = |signed: &bool| if *signed { 's' } else { 'u' };
+        match self {
+            Zero(r) => write!(f, "Zero {}", r),
+            Set(r, x) => write!(f, "Set {},${:x}", r, x),
+            Mov(dst, src) => write!(f, "Mov {},{}", dst, src),
+            Alu {
+                op,
+                dst,
+                src,
+                size,
+                set_flags,
+            } => {
+                write!(f, "{:?}{} {},{}", op, suffix(*size), dst, src)?;
+                if *set_flags {
+                    write!(f, " (flags)")?;
+                }
+                Ok(())
+            }
+            Scale(r, n) => write!(f, "Scale {},{}", r, n),
+            Unscale(r, n) => write!(f, "Unscale {},{}", r, n),
+            Ext(r, s) => write!(f, "Ext{} {}", suffix(*s), r),
+            Mul { signed, dst, src } => {
+                write!(f, "Mul{} {},{}", sign(signed), dst, src)
+            }
+            Shift {
+                op,
+                dst,
+                count,
+                size,
+            } => write!(f, "{:?}{} {},{}", op, suffix(*size), dst, count),
+            Div { signed, dst, src } => {
+                write!(f, "Div{} {},{}", sign(signed), dst, src)
+            }
+            RequestMem(r, s) => write!(f, "RequestMem{} {}", suffix(*s), r),
+            RequestProgram(r, s) => {
+                write!(f, "RequestProgram{} {}", suffix(*s), r)
+            }
+            WriteMem(addr, x, s) => {
+                write!(f, "WriteMem{} {},{}", suffix(*s), addr, x)
+            }
+            TrapIf(c, vector) => write!(f, "TrapIf {},{}", cc(c), vector),
+            BranchIf(c, r) => write!(f, "BranchIf {},{}", cc(c), r),
+            SetIf(c, r) => write!(f, "SetIf {},{}", cc(c), r),
+            DecBranch(c, counter, target) => {
+                write!(f, "DecBranch {},{},{}", cc(c), counter, target)
+            }
+            StopCpu => write!(f, "StopCpu"),
+            ResetDevices => write!(f, "ResetDevices"),
+            SkipIf(c, n) => write!(f, "SkipIf {},{}", cc(c), n),
+            Lock(on) => write!(f, "Lock {}", on),
+            BitField {
+                op,
+                data,
+                pos,
+                width,
+                offset,
+                reg,
+            } => {
+                let field = match data {
+                    Some(d) => format!("d{}", d),
+                    None => Reg::InTmp(3).to_string(),
+                };
+                write!(f, "Bf{:?} {}{{{}:{}}}", op, field, pos, width)?;
+                write!(f, ",d{}", reg)?;
+                if *op == BfOp::Ffo {
+                    write!(f, ",{}", offset)?;
+                }
+                Ok(())
+            }
+            PopFrame(r) => write!(f, "PopFrame {}", r),
+        }
+    }
+}
+
+// Condition code bits, numbered by their position in the CCR.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flag {
+    Carry = 0,
+    Overflow = 1,
+    Zero = 2,
+    Negative = 3,
+    Extend = 4,
+}
+
+impl Flag {
+    fn mask(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+// CPU models, in chronological order so that features can be gated with
+// comparisons.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Variant {
+    M68000,
+    M68010,
+    M68020,
+    M68040,
+}
+
+// What a word or long access at an odd address does.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AlignPolicy {
+    // Take an address error, as the 68000 does.
+    Strict,
+    // Carry on with the access.
+    Lenient,
+}
+
+// What the general registers hold after reset. They are undefined on
+// hardware, a pattern makes reads of never written registers stand out.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PowerOnFill {
+    Zero,
+    Pattern(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct AccessFault {
+    addr: u32,
+    write: bool,
+    fc: FunctionCode,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Running,
+    // After STOP, until an interrupt or a trace wakes the CPU up.
+    Stopped,
+    // A fault hit while processing a bus or address error. Only a reset
+    // gets the CPU out of this.
+    Halted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Pending {
+    Interrupt(u8),
+    Trace,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed,
+    DoubleFault,
+    // An exception asked to break on was raised. It is not taken yet, the
+    // next step does.
+    ExceptionBreak(u8),
+    // The instruction was not run, PC is still at it.
+    DecodeError(DecodeError),
+    // The instruction wrote to this exception vector while they were
+    // watched.
+    VectorWrite(u8),
+}
+
+// Where step_microop left the current instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MicroStepOutcome {
+    // Its last micro-op ran, or it faulted and the exception was taken.
+    Completed,
+    MidInstruction,
+    DecodeError(DecodeError),
+}
+
+// Instructions the emulator gives up on, rather than ones the CPU traps on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    // More extension words than max_ext_words.
+    TooManyExtensionWords,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    // Subtracts for the flags only, the destination is left alone.
+    Cmp,
+    // Copies the source, setting N and Z like the logical operations.
+    Move,
+    And,
+    Or,
+    Eor,
+    // Add or subtract X too. Z is only ever cleared, so that it tells
+    // whether a whole multi-precision result is zero.
+    Addx,
+    Subx,
+    // Subtracts two BCD bytes and X, like Subx for the flags but V.
+    Sbcd,
+    // Adds the source to the destination word and packs its low nibbles
+    // into a byte, or spreads the nibbles of the destination byte into a
+    // word and adds the source. Neither sets flags.
+    Pack,
+    Unpk,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShiftOp {
+    Asl,
+    Asr,
+    Lsl,
+    Lsr,
+    Rol,
+    Ror,
+    // Rotate through X.
+    Roxl,
+    Roxr,
+}
+
+// Bit-field operations, in the order of their opcodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BfOp {
+    Tst,
+    Extu,
+    Chg,
+    Exts,
+    Clr,
+    Ffo,
+    Set,
+    Ins,
+}
+
+// Faults and instructions that could not run stack their own address, traps
+// and interrupts that of the next instruction.
+fn stacks_own_address(vector: u8) -> bool {
+    matches!(vector, 2 | 3 | 4 | 8 | 10 | 11 | 14)
+}
+
+// Plain 32-bit addition without flags, as used for address arithmetic.
+fn addr_add(dst: Reg, src: Reg) -> MicroI {
+    MicroI::Alu {
+        op: AluOp::Add,
+        dst,
+        src,
+        size: Size::Long,
+        set_flags: false,
+    }
+}
+
+enum NextAction {
+    Next,
+    MemRequest(u32, Size),
+    ProgramRequest(u32, Size),
+    MemWrite(u32, u32, Size),
+    Exception(u8),
+    BusLock(bool),
+    ResetDevices,
+}
+
+impl M68K {
+    fn new() -> M68K {
+        M68K {
+            data_r: [0; 8],
+            addr_r: [0; 8],
+            pc: 0,
+            sr: 0x2700,
+            usp: 0,
+            ssp: 0,
+            msp: 0,
+            vbr: 0,
+            sfc: 0,
+            dfc: 0,
+            cacr: 0,
+            caar: 0,
+            intern_r: [0; NB_INTERNAL_REGS + 3],
+            instrs: VecDeque::new(),
+            prefetch: None,
+            cycles: 0,
+            bus_width: BusWidth::Bits16,
+            align_policy: AlignPolicy::Strict,
+            power_on_fill: PowerOnFill::Zero,
+            max_ext_words: MAX_EXT_WORDS,
+            decode_error: None,
+            mem_trace: None,
+            retire_trace: None,
+            fetch_trace: None,
+            vector_watch: None,
+            vector_written: None,
+            state: State::Running,
+            variant: Variant::M68000,
+            ir: 0,
+            ir_pc: 0,
+            fault: AccessFault {
+                addr: 0,
+                write: false,
+                fc: FunctionCode::SupervisorProgram,
+            },
+            ir_words: Vec::new(),
+            irq_lines: 0,
+            trap_handlers: Default::default(),
+            timers: Vec::new(),
+            exception_breaks: Vec::new(),
+            exception_hit: None,
+            held: None,
+            history: VecDeque::new(),
+            history_len: 0,
+            stats: None,
+        }
+    }
+
+    // A CPU in the given mode without going through reset. `a7` is the stack
+    // pointer of that mode.
+    pub fn with_state(pc: u32, sr: u16, a7: u32) -> M68K {
+        let mut cpu = M68K::new();
+        cpu.set_sr(sr);
+        cpu.pc = pc;
+        cpu.addr_r[7] = a7;
+        cpu
+    }
+
+    pub fn snapshot(&self) -> CpuState {
+        CpuState {
+            data_r: self.data_r,
+            addr_r: self.addr_r,
+            pc: self.pc,
+            sr: self.sr,
+        }
+    }
+
+    fn exec(&mut self, m: MicroI) -> NextAction {
+        use NextAction::*;
+        match m {
+            MicroI::Zero(r) => {
+                self.write_reg(r, 0);
+                Next
+            }
+            MicroI::Set(r, x) => {
+                self.write_reg(r, x);
+                Next
+            }
+            MicroI::Mov(dst, src) => {
+                let x = self.read_reg(src);
+                self.write_reg(dst, x);
+                Next
+            }
+            MicroI::Alu {
+                op,
+                dst,
+                src,
+                size,
+                set_flags,
+            } => {
+                let (a, b) = (self.read_reg(dst), self.read_reg(src));
+                let x = self.alu(op, a, b, size, set_flags);
+                if !matches!(op, AluOp::Cmp) {
+                    self.write_reg_sized(dst, x, size);
+                }
+                Next
+            }
+            MicroI::Ext(r, s) => {
+                let x = self.read_reg(r) & s.mask();
+                self.write_reg(r, (x ^ s.msb()).wrapping_sub(s.msb()));
+                Next
+            }
+            MicroI::Scale(r, n) => {
+                // Bits shifted out of the top are lost, as on hardware.
+                let x = self.read_reg(r).wrapping_shl(n as u32);
+                self.write_reg(r, x);
+                Next
+            }
+            MicroI::Unscale(r, n) => {
+                let x = self.read_reg(r).wrapping_shr(n as u32);
+                self.write_reg(r, x);
+                Next
+            }
+            MicroI::Mul { signed, dst, src } => {
+                let (a, b) = (self.read_reg(dst), self.read_reg(src));
+                let x = if signed {
+                    (a as i16 as i32 * b as i16 as i32) as u32
+                } else {
+                    (a & 0xffff) * (b & 0xffff)
+                };
+                self.write_reg(dst, x);
+                self.set_nzvc(x >> 31 != 0, x == 0, false, false);
+                // 68000 timing: 38 + 2n cycles, 4 of which the opcode fetch
+                // took. n counts the ones of the source for MULU, its 01 and
+                // 10 pairs with a zero appended below for MULS.
+                let b = b & 0xffff;
+                let n = if signed {
+                    ((b << 1) ^ b) & 0xffff
+                } else {
+                    b
+                };
+                self.cycles += 34 + 2 * n.count_ones() as u64;
+                Next
+            }
+            MicroI::Shift {
+                op,
+                dst,
+                count,
+                size,
+            } => {
+                let n = self.read_reg(count) % 64;
+                let x = self.shift(op, self.read_reg(dst), n, size);
+                self.write_reg_sized(dst, x, size);
+                Next
+            }
+            MicroI::Div { signed, dst, src } => {
+                let (a, b) = (self.read_reg(dst), self.read_reg(src) as u16);
+                if b == 0 {
+                    self.set_flag(Flag::Carry, false);
+                    return Exception(5);
+                }
+                // Overflow is told before dividing: unsigned, from the high
+                // word of the dividend; signed, from the magnitudes, the
+                // quotient being truncated toward zero.
+                let fits = if signed {
+                    let (n, d) = (a as i32 as i64, b as i16 as i64);
+                    let q = n.abs() / d.abs();
+                    q <= 0x7fff || q == 0x8000 && (n < 0) != (d < 0)
+                } else {
+                    a >> 16 < b as u32
+                };
+                if !fits {
+                    // The destination is left untouched on overflow.
+                    self.set_nzvc(true, false, true, false);
+                    return Next;
+                }
+                // Rust's / and % truncate like the 68000, the remainder
+                // taking the sign of the dividend: -7 / 2 is -3, rest -1.
+                let (q, r) = if signed {
+                    let (a, b) = (a as i32 as i64, b as i16 as i64);
+                    (a / b, a % b)
+                } else {
+                    ((a / b as u32) as i64, (a % b as u32) as i64)
+                };
+                self.write_reg(dst, (r as u32) << 16 | (q as u32 & 0xffff));
+                self.set_nzvc(q & 0x8000 != 0, q as u16 == 0, false, false);
+                Next
+            }
+            MicroI::RequestMem(addr, s) => {
+                MemRequest(self.read_reg(addr), s)
+            }
+            MicroI::RequestProgram(addr, s) => {
+                ProgramRequest(self.read_reg(addr), s)
+            }
+            MicroI::WriteMem(addr, x, s) => {
+                MemWrite(self.read_reg(addr), self.read_reg(x), s)
+            }
+            MicroI::BranchIf(cc, target) => {
+                if self.eval_condition(cc) {
+                    self.pc = self.read_reg(target);
+                }
+                Next
+            }
+            MicroI::SetIf(cc, r) => {
+                let x = if self.eval_condition(cc) { 0xff } else { 0 };
+                self.write_reg_sized(r, x, Size::Byte);
+                Next
+            }
+            MicroI::DecBranch(cc, counter, target) => {
+                if !self.eval_condition(cc) {
+                    let n = self.read_reg(counter).wrapping_sub(1);
+                    self.write_reg_sized(counter, n, Size::Word);
+                    if n & 0xffff != 0xffff {
+                        self.pc = self.read_reg(target);
+                    }
+                }
+                Next
+            }
+            MicroI::Lock(l) => BusLock(l),
+            MicroI::BitField {
+                op,
+                data,
+                pos,
+                width,
+                offset,
+                reg,
+            } => {
+                self.bitfield(op, data, pos, width, offset, reg);
+                Next
+            }
+            MicroI::PopFrame(r) => {
+                let words = match self.read_reg(r) >> 12 & 0xf {
+                    // A throwaway frame: its SR, with M set, switches to the
+                    // master stack, and RTE starts over from the frame there.
+                    0x1 if self.variant >= Variant::M68020 => {
+                        self.addr_r[7] = self.addr_r[7].wrapping_add(8);
+                        self.set_sr(self.read_reg(Reg::InTmp(0)) as u16);
+                        self.instrs.clear();
+                        self.gen_rte();
+                        return Next;
+                    }
+                    0x0 => 4,
+                    0x2 => 6,
+                    0x7 => 30,
+                    0x8 => 29,
+                    0xa => 16,
+                    0xb => 46,
+                    _ => return Exception(14),
+                };
+                self.addr_r[7] = self.addr_r[7].wrapping_add(words * 2);
+                Next
+            }
+            MicroI::TrapIf(cc, v) => {
+                if self.eval_condition(cc) {
+                    Exception(v)
+                } else {
+                    Next
+                }
+            }
+            MicroI::ResetDevices => {
+                self.cycles += RESET_CYCLES;
+                NextAction::ResetDevices
+            }
+            MicroI::StopCpu => {
+                self.state = State::Stopped;
+                Next
+            }
+            MicroI::SkipIf(cc, n) => {
+                if self.eval_condition(cc) {
+                    self.instrs.drain(..n.min(self.instrs.len()));
+                }
+                Next
+            }
+        }
+    }
+
+    // Computes `a op b` over `size` bits, optionally updating the condition
+    // codes. X follows C for additions and subtractions but not for CMP.
+    fn alu(&mut self, op: AluOp, a: u32, b: u32, s: Size, flags: bool) -> u32 {
+        let (m, msb) = (s.mask(), s.msb());
+        let (a, b) = (a & m, b & m);
+        let (x, v, c) = match op {
+            AluOp::Add => {
+                let x = a.wrapping_add(b) & m;
+                (x, (a ^ x) & (b ^ x) & msb != 0, x < a)
+            }
+            AluOp::Sub | AluOp::Cmp => {
+                let x = a.wrapping_sub(b) & m;
+                (x, (a ^ b) & (a ^ x) & msb != 0, b > a)
+            }
+            AluOp::Addx | AluOp::Subx => {
+                let ext = self.get_flag(Flag::Extend) as u64;
+                let (a, b) = (a as u64, b as u64);
+                let x = if op == AluOp::Addx {
+                    a + b + ext
+                } else {
+                    a.wrapping_sub(b + ext)
+                };
+                let (c, x) = (x > m as u64, x as u32 & m);
+                let v = if op == AluOp::Addx {
+                    (a as u32 ^ x) & (b as u32 ^ x)
+                } else {
+                    (a as u32 ^ b as u32) & (a as u32 ^ x)
+                };
+                (x, v & msb != 0, c)
+            }
+            AluOp::Sbcd => {
+                // Digits are subtracted one at a time, a borrow out of one
+                // taking 6 off to skip the codes above 9.
+                let ext = self.get_flag(Flag::Extend) as u32;
+                let mut x = (a & 0xf).wrapping_sub((b & 0xf) + ext);
+                if x > 9 {
+                    x = x.wrapping_sub(6);
+                }
+                x = x.wrapping_add(a & 0xf0).wrapping_sub(b & 0xf0);
+                let c = x > 0x99;
+                if c {
+                    x = x.wrapping_add(0xa0);
+                }
+                (x & 0xff, false, c)
+            }
+            AluOp::Pack => {
+                let x = a.wrapping_add(b);
+                ((x >> 4 & 0xf0) | (x & 0xf), false, false)
+            }
+            AluOp::Unpk => {
+                let x = (a & 0xf0) << 4 | (a & 0xf);
+                (x.wrapping_add(b) & m, false, false)
+            }
+            AluOp::Move => (b, false, false),
+            AluOp::And => (a & b, false, false),
+            AluOp::Or => (a | b, false, false),
+            AluOp::Eor => (a ^ b, false, false),
+        };
+        if flags {
+            let extended =
+                matches!(op, AluOp::Addx | AluOp::Subx | AluOp::Sbcd);
+            let z = x == 0 && (!extended || self.get_flag(Flag::Zero));
+            self.set_nzvc(x & msb != 0, z, v, c);
+            if extended || matches!(op, AluOp::Add | AluOp::Sub) {
+                self.set_flag(Flag::Extend, c);
+            }
+        }
+        x
+    }
+
+    // Runs `op` on a bit field for BitField. N and Z follow the field, or
+    // the inserted value for BFINS.
+    fn bitfield(
+        &mut self,
+        op: BfOp,
+        data: Option<usize>,
+        pos: u32,
+        width: u32,
+        offset: u32,
+        reg: usize,
+    ) {
+        use BfOp::*;
+        // The operand left-justified in 64 bits, with the field `at` bits
+        // from the top.
+        let (x, at) = match data {
+            Some(r) => ((self.data_r[r].rotate_left(pos) as u64) << 32, 0),
+            None => {
+                let mut x = 0;
+                for i in 0..5 {
+                    let byte = self.read_reg(Reg::InTmp(3 + i)) & 0xff;
+                    x |= (byte as u64) << (56 - 8 * i);
+                }
+                (x, pos)
+            }
+        };
+        let shift = 64 - width - at;
+        let ones = u64::MAX >> (64 - width);
+        let field = (x >> shift & ones) as u32;
+        let value = match op {
+            Ins => self.data_r[reg] & ones as u32,
+            _ => field,
+        };
+        self.set_nzvc(value >> (width - 1) & 1 != 0, value == 0, false, false);
+        let x = match op {
+            Tst => return,
+            Extu => return self.data_r[reg] = field,
+            Exts => {
+                let n = 32 - width;
+                return self.data_r[reg] = ((field << n) as i32 >> n) as u32;
+            }
+            Ffo => {
+                let first = field.leading_zeros() - (32 - width);
+                return self.data_r[reg] = offset.wrapping_add(first);
+            }
+            Chg => x ^ ones << shift,
+            Clr => x & !(ones << shift),
+            Set => x | ones << shift,
+            Ins => x & !(ones << shift) | (value as u64) << shift,
+        };
+        match data {
+            Some(r) => self.data_r[r] = ((x >> 32) as u32).rotate_right(pos),
+            None => {
+                for i in 0..5 {
+                    let byte = (x >> (56 - 8 * i)) as u32 & 0xff;
+                    self.write_reg(Reg::InTmp(3 + i), byte);
+                }
+            }
+        }
+    }
+
+    // Shifts `x` over `s` bits one position at a time, `n` times, and sets
+    // the flags. C is the last bit shifted out and X follows it, except for
+    // ROL and ROR which leave X alone. A zero count clears C, but ROXL and
+    // ROXR copy X into it. V is only set by ASL, when the sign changed at any
+    // point.
+    fn shift(&mut self, op: ShiftOp, x: u32, n: u32, s: Size) -> u32 {
+        use ShiftOp::*;
+        let (m, msb) = (s.mask(), s.msb());
+        let mut x = x & m;
+        let mut ext = self.get_flag(Flag::Extend);
+        let (mut c, mut v) = (false, false);
+        for _ in 0..n {
+            let (hi, lo) = (x & msb != 0, x & 1 != 0);
+            let (out, shifted_in) = match op {
+                Asl | Lsl => (hi, false),
+                Asr => (lo, hi),
+                Lsr => (lo, false),
+                Rol => (hi, hi),
+                Ror => (lo, lo),
+                Roxl | Roxr => (if op == Roxl { hi } else { lo }, ext),
+            };
+            x = match op {
+                Asl | Lsl | Rol | Roxl => (x << 1 & m) | shifted_in as u32,
+                _ => x >> 1 | if shifted_in { msb } else { 0 },
+            };
+            c = out;
+            ext = out;
+            v |= op == Asl && (x & msb != 0) != hi;
+        }
+        if matches!(op, Roxl | Roxr) {
+            c = ext;
+        }
+        self.set_nzvc(x & msb != 0, x == 0, v, c);
+        if n != 0 && !matches!(op, Rol | Ror) {
+            self.set_flag(Flag::Extend, c);
+        }
+        x
+    }
+
+    // Updates N, Z, V and C, leaving X alone.
+    fn set_nzvc(&mut self, n: bool, z: bool, v: bool, c: bool) {
+        self.set_flag(Flag::Negative, n);
+        self.set_flag(Flag::Zero, z);
+        self.set_flag(Flag::Overflow, v);
+        self.set_flag(Flag::Carry, c);
+    }
+
+    fn get_flag(&self, f: Flag) -> bool {
+        self.sr & f.mask() != 0
+    }
+
+    fn set_flag(&mut self, f: Flag, v: bool) {
+        if v {
+            self.sr |= f.mask();
+        } else {
+            self.sr &= !f.mask();
+        }
+    }
+
+    // Tests one of the 16 condition codes found in Bcc, DBcc and Scc opcodes.
+    // This only reads the flags, so it is also fit to ask whether a branch
+    // would be taken without executing it.
+    fn eval_condition(&self, cc: u8) -> bool {
+        let c = self.get_flag(Flag::Carry);
+        let v = self.get_flag(Flag::Overflow);
+        let z = self.get_flag(Flag::Zero);
+        let n = self.get_flag(Flag::Negative);
+        match cc & 0xf {
+            0x0 => true,          // T
+            0x1 => false,         // F
+            0x2 => !c && !z,      // HI
+            0x3 => c || z,        // LS
+            0x4 => !c,            // CC
+            0x5 => c,             // CS
+            0x6 => !z,            // NE
+            0x7 => z,             // EQ
+            0x8 => !v,            // VC
+            0x9 => v,             // VS
+            0xa => !n,            // PL
+            0xb => n,             // MI
+            0xc => n == v,        // GE
+            0xd => n != v,        // LT
+            0xe => !z && n == v,  // GT
+            _ => z || n != v,     // LE
+        }
+    }
+
+    fn supervisor(&self) -> bool {
+        self.sr & 0x2000 != 0
+    }
+
+    // Writes the status register, swapping stack pointers when the S bit
+    // changes.
+    fn set_sr(&mut self, sr: u16) {
+        // The M bit is new with the 68020.
+        let sr = match self.variant {
+            Variant::M68000 | Variant::M68010 => sr & 0xa71f,
+            _ => sr & 0xb71f,
+        };
+        let stack = |sr: u16| if sr & 0x2000 == 0 { 0 } else { sr & 0x3000 };
+        if stack(sr) != stack(self.sr) {
+            let a7 = self.addr_r[7];
+            *self.stack_slot(self.sr) = a7;
+            self.addr_r[7] = *self.stack_slot(sr);
+        }
+        self.sr = sr;
+    }
+
+    // Where the stack pointer that `sr` selects is kept while not in A7.
+    fn stack_slot(&mut self, sr: u16) -> &mut u32 {
+        match (sr & 0x2000 != 0, sr & 0x1000 != 0) {
+            (false, _) => &mut self.usp,
+            (true, false) => &mut self.ssp,
+            (true, true) => &mut self.msp,
+        }
+    }
+
+    // Whether A7 is the master stack pointer, or the interrupt one.
+    fn in_a7(&self, master: bool) -> bool {
+        self.supervisor() && (self.sr & 0x1000 != 0) == master
+    }
+
+    fn read_reg(&self, r: Reg) -> u32 {
+        match r {
+            Reg::D(r) => self.data_r[r],
+            Reg::A(r) => self.addr_r[r],
+            Reg::PC => self.pc,
+            Reg::CCR => (self.sr & 0xff) as u32,
+            Reg::SR => self.sr as u32,
+            Reg::USP => self.usp,
+            Reg::MSP | Reg::ISP if self.in_a7(r == Reg::MSP) => self.addr_r[7],
+            Reg::MSP => self.msp,
+            Reg::ISP => self.ssp,
+            Reg::VBR => self.vbr,
+            Reg::SFC => self.sfc as u32,
+            Reg::DFC => self.dfc as u32,
+            Reg::CACR => self.cacr,
+            Reg::CAAR => self.caar,
+            Reg::InTmp(r) => self.intern_r[r],
+            Reg::In0 => self.intern_r[NB_INTERNAL_REGS],
+            Reg::In1 => self.intern_r[NB_INTERNAL_REGS + 1],
+            Reg::IOBuffer => self.intern_r[NB_INTERNAL_REGS + 2],
+            Reg::Immediate(x) => x as u32,
+        }
+    }
+
+    fn write_reg(&mut self, r: Reg, x: u32) {
+        match r {
+            Reg::D(r) => self.data_r[r] = x,
+            Reg::A(r) => self.addr_r[r] = x,
+            Reg::PC => self.pc = x,
+            Reg::CCR => self.sr = (self.sr & 0xff00) | (x as u16 & 0x1f),
+            Reg::SR => self.set_sr(x as u16),
+            Reg::USP => self.usp = x,
+            Reg::MSP | Reg::ISP if self.in_a7(r == Reg::MSP) => {
+                self.addr_r[7] = x
+            }
+            Reg::MSP => self.msp = x,
+            Reg::ISP => self.ssp = x,
+            Reg::VBR => self.vbr = x,
+            Reg::SFC => self.sfc = x as u8 & 7,
+            Reg::DFC => self.dfc = x as u8 & 7,
+            Reg::CACR => self.cacr = x,
+            Reg::CAAR => self.caar = x,
+            Reg::InTmp(r) => self.intern_r[r] = x,
+            Reg::In0 => self.intern_r[NB_INTERNAL_REGS] = x,
+            Reg::In1 => self.intern_r[NB_INTERNAL_REGS + 1] = x,
+            Reg::IOBuffer => self.intern_r[NB_INTERNAL_REGS + 2] = x,
+            Reg::Immediate(_) => unreachable!(),
+        }
+    }
+
+    // Reset exception: enters supervisor mode and loads the initial SSP and
+    // PC from the first two vectors. What hardware leaves undefined is
+    // cleared, or filled as power_on_fill says, so that runs are repeatable.
+    pub fn reset(&mut self, bus: &mut impl Bus) {
+        self.instrs.clear();
+        self.prefetch = None;
+        self.state = State::Running;
+        self.sr = 0x2700;
+        self.vbr = 0;
+        self.sfc = 0;
+        self.dfc = 0;
+        self.cacr = 0;
+        self.caar = 0;
+        self.intern_r = [0; NB_INTERNAL_REGS + 3];
+        self.ir = 0;
+        self.ir_pc = 0;
+        self.ir_words.clear();
+        self.decode_error = None;
+        self.held = None;
+        self.fault = AccessFault {
+            addr: 0,
+            write: false,
+            fc: FunctionCode::SupervisorProgram,
+        };
+        let x = match self.power_on_fill {
+            PowerOnFill::Zero => 0,
+            PowerOnFill::Pattern(x) => x,
+        };
+        self.data_r = [x; 8];
+        self.addr_r = [x; 8];
+        self.usp = x;
+        self.msp = x;
+        let fc = FunctionCode::SupervisorProgram;
+        let res = self.read_mem(bus, 0, Size::Long, fc).and_then(|sp| {
+            self.addr_r[7] = sp;
+            self.read_mem(bus, 4, Size::Long, fc)
+        });
+        match res {
+            Ok(pc) => self.pc = pc,
+            Err(_) => self.state = State::Halted,
+        }
+    }
+
+    pub fn step(&mut self, bus: &mut impl Bus) -> StepOutcome {
+        // Left over by micro-steps.
+        self.vector_written = None;
+        if self.history_len != 0 {
+            if self.history.len() == self.history_len {
+                self.history.pop_front();
+            }
+            let timers = self.timers.iter();
+            let timers = timers.map(|t| (t.deadline, t.raised)).collect();
+            self.history.push_back(Undo {
+                cpu: self.snapshot(),
+                usp: self.usp,
+                ssp: self.ssp,
+                msp: self.msp,
+                vbr: self.vbr,
+                sfc: self.sfc,
+                dfc: self.dfc,
+                cacr: self.cacr,
+                caar: self.caar,
+                cycles: self.cycles,
+                state: self.state,
+                irq_lines: self.irq_lines,
+                held: self.held,
+                timers,
+                writes: Vec::new(),
+            });
+        }
+        if let Some((vector, level)) = self.held.take() {
+            match level {
+                Some(level) => self.interrupt(bus, level, vector),
+                None => self.take_exception(bus, vector),
+            }
+        } else if let State::Stopped = self.state {
+            // The interrupt lines are sampled once per idle bus cycle.
+            self.cycles += 4;
+            self.tick_timers();
+            self.service_pending(bus, false);
+        } else if let State::Running = self.state {
+            // T is sampled before the instruction starts, clearing it within
+            // the instruction still traces that instruction.
+            let mut trace = self.sr & 0x8000 != 0;
+            self.ir_pc = self.pc;
+            self.ir_words.clear();
+            let start = self.cycles;
+            let fetched = self.fetch_word(bus);
+            let res = fetched
+                .and_then(|op| {
+                    self.ir = op;
+                    self.decode_opcode(bus, op)
+                })
+                .and_then(|_| {
+                    self.prefetch_next(bus);
+                    self.run_microcode(bus)
+                });
+            if let Some(e) = self.decode_error.take() {
+                self.pc = self.ir_pc;
+                self.instrs.clear();
+                return StepOutcome::DecodeError(e);
+            }
+            // Instructions that trap are done and still get traced, faulting
+            // and illegal ones never completed.
+            let retired = match res {
+                Ok(()) => true,
+                Err(v) => matches!(v, 5..=7 | 32..=47),
+            };
+            // Decoded from the words it was run from, which it may have
+            // overwritten since.
+            if let (Some(hook), true) = (&mut self.retire_trace, retired) {
+                let (pc, words) = (self.ir_pc, &self.ir_words);
+                if let Some(d) = disasm::decode_words(pc, words, self.variant) {
+                    hook(&d);
+                }
+            }
+            if let Err(v) = res {
+                trace &= retired;
+                self.exception(bus, v);
+            }
+            let class = fetched.ok().and_then(decode::opcode_class);
+            if let (Some(stats), Some(class)) = (&mut self.stats, class) {
+                let entry = stats.per_class.entry(class).or_default();
+                entry.count += 1;
+                entry.cycles += self.cycles - start;
+            }
+            self.tick_timers();
+            self.service_pending(bus, trace);
+        }
+        let hit = self.exception_hit.take();
+        let written = self.vector_written.take();
+        match (&self.state, hit, written) {
+            (State::Halted, _, _) => StepOutcome::DoubleFault,
+            (_, Some(v), _) => StepOutcome::ExceptionBreak(v),
+            (_, None, Some(v)) => StepOutcome::VectorWrite(v),
+            (_, None, None) => StepOutcome::Executed,
+        }
+    }
+
+    // Keeps what is needed to undo the last `steps` steps. Memory is read
+    // before it is written, which devices may not like.
+    pub fn record_history(&mut self, steps: usize) {
+        self.history_len = steps;
+        self.history.clear();
+    }
+
+    // Undoes the last recorded step, returning false when there is none.
+    // Memory is restored with debugger accesses.
+    pub fn step_back(&mut self, bus: &mut impl Bus) -> bool {
+        let Some(undo) = self.history.pop_back() else {
+            return false;
+        };
+        for &(addr, s, x) in undo.writes.iter().rev() {
+            let _ = bus::poke(bus, addr, s, x);
+        }
+        self.data_r = undo.cpu.data_r;
+        self.addr_r = undo.cpu.addr_r;
+        self.pc = undo.cpu.pc;
+        self.sr = undo.cpu.sr;
+        self.usp = undo.usp;
+        self.ssp = undo.ssp;
+        self.msp = undo.msp;
+        self.vbr = undo.vbr;
+        self.sfc = undo.sfc;
+        self.dfc = undo.dfc;
+        self.cacr = undo.cacr;
+        self.caar = undo.caar;
+        self.cycles = undo.cycles;
+        self.state = undo.state;
+        self.irq_lines = undo.irq_lines;
+        self.held = undo.held;
+        let saved = undo.timers.iter();
+        for (t, &(deadline, raised)) in self.timers.iter_mut().zip(saved) {
+            t.deadline = deadline;
+            t.raised = raised;
+        }
+        self.instrs.clear();
+        self.prefetch = None;
+        true
+    }
+
+    pub fn collect_stats(&mut self) {
+        self.stats = Some(ExecStats::default());
+    }
+
+    // The statistics gathered since the last call, starting over.
+    pub fn take_stats(&mut self) -> Option<ExecStats> {
+        self.stats.as_mut().map(std::mem::take)
+    }
+
+    // Calls `watch` with each exception vector written to, the steps
+    // writing them ending with VectorWrite.
+    pub fn watch_vectors(&mut self, watch: VectorHook) {
+        self.vector_watch = Some(watch);
+    }
+
+    // Makes steps raising exception `vector` stop before it is taken.
+    pub fn break_on_exception(&mut self, vector: u8) {
+        if !self.exception_breaks.contains(&vector) {
+            self.exception_breaks.push(vector);
+        }
+    }
+
+    // Steps over the instruction at PC: after a BSR or JSR, runs until it
+    // returns, which is when PC is back after it with the return address
+    // popped. Anything else is a single step. Gives up after `max` steps.
+    pub fn step_over(&mut self, bus: &mut impl Bus, max: usize) -> StepOutcome {
+        use decode::Instr::*;
+        let taking_held = self.held.is_some();
+        let outcome = self.step(bus);
+        let call = matches!(decode::decode_instruction(self.ir), Bsr | Jsr);
+        let call = call && !taking_held;
+        if !call || !matches!(outcome, StepOutcome::Executed) {
+            return outcome;
+        }
+        let ret = self.ir_pc.wrapping_add(2 * self.ir_words.len() as u32);
+        let sp = self.addr_r[7];
+        for _ in 1..max {
+            if self.pc == ret && self.addr_r[7] > sp {
+                break;
+            }
+            let outcome = self.step(bus);
+            if !matches!(outcome, StepOutcome::Executed) {
+                return outcome;
+            }
+        }
+        StepOutcome::Executed
+    }
+
+    pub fn set_trap_handler(&mut self, n: u8, handler: TrapHandler) {
+        self.trap_handlers[n as usize & 0xf] = Some(handler);
+    }
+
+    // TRAP #n: runs the handler registered for it, or takes the exception.
+    fn trap(&mut self, bus: &mut impl Bus, n: u8) -> Result<(), u8> {
+        let Some(mut handler) = self.trap_handlers[n as usize].take() else {
+            return Err(32 + n);
+        };
+        handler(self, bus);
+        // The handler may have replaced itself.
+        self.trap_handlers[n as usize].get_or_insert(handler);
+        Ok(())
+    }
+
+    // Decodes and runs the single instruction in `words`, extension words
+    // included, as if it sat at PC. Other accesses go to `bus`.
+    pub fn execute_opcode(
+        &mut self,
+        words: &[u16],
+        bus: &mut impl Bus,
+    ) -> Result<(), u8> {
+        let mut bus = WordStream {
+            base: self.pc,
+            words,
+            bus,
+        };
+        self.ir_pc = self.pc;
+        self.ir_words.clear();
+        self.prefetch = None;
+        self.decode_error = None;
+        let op = self.fetch_word(&mut bus)?;
+        self.ir = op;
+        self.decode_opcode(&mut bus, op)?;
+        self.run_microcode(&mut bus)
+    }
+
+    // Makes a device request an interrupt at `level` every `period_cycles`,
+    // answering its acknowledge with `vector`.
+    pub fn add_periodic(&mut self, period_cycles: u64, level: u8, vector: u8) {
+        let period = period_cycles.max(1);
+        self.timers.push(Timer {
+            period,
+            deadline: self.cycles + period,
+            level: level & 7,
+            vector,
+            raised: false,
+        });
+    }
+
+    fn tick_timers(&mut self) {
+        for t in &mut self.timers {
+            if self.cycles >= t.deadline {
+                t.raised = true;
+                // Ticks missed while the request was pending are lost.
+                let missed = (self.cycles - t.deadline) / t.period;
+                t.deadline += (missed + 1) * t.period;
+            }
+        }
+    }
+
+    // Asserts or deasserts the interrupt request line of `level`, 1 to 7.
+    // The line stays pending until deasserted or its interrupt is taken.
+    pub fn set_irq(&mut self, level: u8, asserted: bool) {
+        let bit = 1 << (level & 7) & !1;
+        if asserted {
+            self.irq_lines |= bit;
+        } else {
+            self.irq_lines &= !bit;
+        }
+    }
+
+    // Highest level requested by an interrupt line or a timer.
+    fn irq_level(&self) -> u8 {
+        let lines = self.irq_lines.checked_ilog2().unwrap_or(0) as u8;
+        let timers = self.timers.iter().filter(|t| t.raised);
+        timers.map(|t| t.level).fold(lines, u8::max)
+    }
+
+    // Acknowledges a timer interrupting at `level`, returning its vector.
+    fn ack_timer(&mut self, level: u8) -> Option<u8> {
+        let t = self
+            .timers
+            .iter_mut()
+            .find(|t| t.raised && t.level == level)?;
+        t.raised = false;
+        Some(t.vector)
+    }
+
+    // Pending exception conditions at an instruction boundary, from the
+    // highest priority down. Reset, bus and address errors, traps and
+    // illegal opcodes all come before these as they are taken as soon as
+    // they happen.
+    fn arbitrate(&self, trace: bool) -> Vec<Pending> {
+        let mut pending = Vec::new();
+        let level = self.irq_level();
+        // Level 7 cannot be masked.
+        if level > (self.sr >> 8 & 7) as u8 || level == 7 {
+            pending.push(Pending::Interrupt(level));
+        }
+        if trace {
+            pending.push(Pending::Trace);
+        }
+        pending.sort();
+        pending
+    }
+
+    // Processes the pending exceptions lowest priority first. Each frame
+    // ends up under the next one, so the highest priority handler runs
+    // first and the others resume when it returns, like on the 68000 where
+    // a trace pending with an interrupt is stacked then preempted.
+    fn service_pending(&mut self, bus: &mut impl Bus, trace: bool) {
+        for p in self.arbitrate(trace).into_iter().rev() {
+            if matches!(self.state, State::Halted) || self.held.is_some() {
+                return;
+            }
+            match p {
+                Pending::Trace => self.exception(bus, 9),
+                Pending::Interrupt(level) => {
+                    let vector = self.ack_timer(level).unwrap_or_else(|| {
+                        self.irq_lines &= !(1 << level);
+                        24 + level
+                    });
+                    if !self.hold(vector, Some(level)) {
+                        self.interrupt(bus, level, vector);
+                    }
+                }
+            }
+        }
+    }
+
+    fn interrupt(&mut self, bus: &mut impl Bus, level: u8, vector: u8) {
+        self.take_exception(bus, vector);
+        self.sr = (self.sr & !0x0700) | (level as u16) << 8;
+        if self.sr & 0x1000 != 0 {
+            self.throwaway_frame(bus, vector);
+        }
+    }
+
+    // An interrupt taken with M set leaves its frame on the master stack,
+    // then clears M and stacks a copy of it, of format 1, on the interrupt
+    // stack where the handler runs. That copy holds the SR and PC the
+    // handler starts with.
+    fn throwaway_frame(&mut self, bus: &mut impl Bus, vector: u8) {
+        let (sr, pc) = (self.sr, self.pc);
+        self.set_sr(sr & !0x1000);
+        let fv = vector as u16 * 4;
+        let frame = [sr, (pc >> 16) as u16, pc as u16, 0x1000 | fv];
+        for w in frame.into_iter().rev() {
+            if let Err(v) = self.push(bus, Size::Word, w as u32) {
+                self.take_exception(bus, v);
+                return;
+            }
+        }
+    }
+
+    fn fetch_word(&mut self, bus: &mut impl Bus) -> Result<u16, u8> {
+        self.check_ext_words(self.pc)?;
+        let fc = self.space(true);
+        let x = match self.prefetch.take() {
+            Some((addr, space, x)) if addr == self.pc && space == fc => x,
+            _ => self.read_mem(bus, self.pc, Size::Word, fc)? as u16,
+        };
+        if let Some(hook) = &mut self.fetch_trace {
+            hook(self.pc, x);
+        }
+        self.ir_words.push(x);
+        self.pc = self.pc.wrapping_add(2);
+        Ok(x)
+    }
+
+    // Reads the word at PC into the prefetch queue, as the 68000 does while
+    // an instruction executes. A fault is left for the actual fetch to take.
+    fn prefetch_next(&mut self, bus: &mut impl Bus) {
+        let fc = self.space(true);
+        self.prefetch = None;
+        if self.pc & 1 == 0 {
+            let x = self.read_bus(bus, self.pc, Size::Word, fc);
+            self.prefetch = x.ok().map(|x| (self.pc, fc, x as u16));
+        }
+    }
+
+    // Drops the word read ahead, for hosts that patch the code at PC between
+    // steps and want the change seen.
+    pub fn flush_prefetch(&mut self) {
+        self.prefetch = None;
+    }
+
+    // Fails if the word at `pc` is past the last extension word the
+    // instruction started at ir_pc may have. The vector returned only
+    // unwinds the decoder, stepping reports decode_error instead.
+    fn check_ext_words(&mut self, pc: u32) -> Result<(), u8> {
+        if pc.wrapping_sub(self.ir_pc) > 2 * self.max_ext_words {
+            self.decode_error = Some(DecodeError::TooManyExtensionWords);
+            return Err(4);
+        }
+        Ok(())
+    }
+
+    pub fn set_max_ext_words(&mut self, n: u32) {
+        self.max_ext_words = n;
+    }
+
+    // Bits8 for a 68008, which splits every word into two byte cycles.
+    pub fn set_bus_width(&mut self, width: BusWidth) {
+        self.bus_width = width;
+    }
+
+    pub fn set_align_policy(&mut self, policy: AlignPolicy) {
+        self.align_policy = policy;
+    }
+
+    // Applies from the next reset on.
+    pub fn set_power_on_fill(&mut self, fill: PowerOnFill) {
+        self.power_on_fill = fill;
+    }
+
+    fn fetch_long(&mut self, bus: &mut impl Bus) -> Result<u32, u8> {
+        let hi = self.fetch_word(bus)? as u32;
+        Ok(hi << 16 | self.fetch_word(bus)? as u32)
+    }
+
+    fn exception(&mut self, bus: &mut impl Bus, vector: u8) {
+        if !self.hold(vector, None) {
+            self.take_exception(bus, vector);
+        }
+    }
+
+    // Keeps exception `vector` from being taken if it is to break on, so
+    // that the step stops before anything is stacked. Only the first one of
+    // a step is held, nothing else is taken after it.
+    fn hold(&mut self, vector: u8, level: Option<u8>) -> bool {
+        if self.held.is_some() || !self.exception_breaks.contains(&vector) {
+            return false;
+        }
+        self.held = Some((vector, level));
+        self.exception_hit = Some(vector);
+        // Left at the instruction the handler would return to.
+        if stacks_own_address(vector) {
+            self.pc = self.ir_pc;
+        }
+        true
+    }
+
+    fn take_exception(&mut self, bus: &mut impl Bus, vector: u8) {
+        if let State::Stopped = self.state {
+            // Leaving the stopped state takes one more idle bus cycle.
+            self.state = State::Running;
+            self.cycles += STOP_WAKEUP_CYCLES;
+        }
+        let sr = self.sr;
+        let mut vector = vector;
+        while let Err(v) = self.enter_exception(bus, vector, sr) {
+            // A fault while stacking a bus or address error is a double
+            // fault: the 68000 gives up and halts.
+            if vector == 2 || vector == 3 {
+                self.state = State::Halted;
+                return;
+            }
+            vector = v;
+        }
+    }
+
+    fn enter_exception(
+        &mut self,
+        bus: &mut impl Bus,
+        vector: u8,
+        sr: u16,
+    ) -> Result<(), u8> {
+        self.set_sr((self.sr | 0x2000) & !0x8000);
+        if stacks_own_address(vector) {
+            self.pc = self.ir_pc;
+        }
+        let frame = match vector {
+            2 | 3 => self.fault_frame(vector, sr),
+            _ => self.short_frame(vector, sr),
+        };
+        for w in frame.into_iter().rev() {
+            self.push(bus, Size::Word, w as u32)?;
+        }
+        let fc = FunctionCode::SupervisorData;
+        let addr = self.vbr.wrapping_add(vector as u32 * 4);
+        self.pc = self.read_mem(bus, addr, Size::Long, fc)?;
+        Ok(())
+    }
+
+    // Exception stack frames are built as words from the top of the stack
+    // down. The 68000 only stacks SR and PC, later models add the
+    // format/vector word.
+    fn short_frame(&self, vector: u8, sr: u16) -> Vec<u16> {
+        let mut frame = vec![sr, (self.pc >> 16) as u16, self.pc as u16];
+        if self.variant >= Variant::M68010 {
+            frame.push(vector as u16 * 4);
+        }
+        frame
+    }
+
+    // Bus and address error frames, with the details of the faulting access
+    // in each model's own layout. All status words hold its function code,
+    // whether it was a read, and whether it fetched the instruction stream:
+    // the 68000's I/N bit is clear for fetches, the 68010's IF bit set.
+    fn fault_frame(&self, vector: u8, sr: u16) -> Vec<u16> {
+        let AccessFault { addr, write, fc } = self.fault;
+        let (hi, lo) = ((addr >> 16) as u16, addr as u16);
+        let (pc_hi, pc_lo) = ((self.pc >> 16) as u16, self.pc as u16);
+        let read = !write as u16;
+        let fetch = matches!(
+            fc,
+            FunctionCode::UserProgram | FunctionCode::SupervisorProgram
+        ) as u16;
+        let fc = fc as u16;
+        let fv = vector as u16 * 4;
+        let (mut frame, len) = match self.variant {
+            Variant::M68000 => {
+                let ssw = read << 4 | (1 - fetch) << 3 | fc;
+                (vec![ssw, hi, lo, self.ir, sr, pc_hi, pc_lo], 7)
+            }
+            Variant::M68010 => {
+                let ssw = fetch << 13 | read << 8 | fc;
+                let f = vec![sr, pc_hi, pc_lo, 0x8000 | fv, ssw, hi, lo];
+                ([f, vec![0; 5], vec![self.ir]].concat(), 29)
+            }
+            Variant::M68020 => {
+                // DF for a data access. A faulted fetch sets FB and FC, the
+                // pipeline stages are not modeled so both are to refetch.
+                let stages = match fetch {
+                    0 => 0x0100,
+                    _ => 0xc000,
+                };
+                let ssw = stages | read << 6 | fc;
+                let f = vec![sr, pc_hi, pc_lo, 0xa000 | fv, 0, ssw];
+                ([f, vec![self.ir, self.ir, hi, lo]].concat(), 16)
+            }
+            Variant::M68040 if vector == 3 => {
+                (vec![sr, pc_hi, pc_lo, 0x2000 | fv, hi, lo], 6)
+            }
+            Variant::M68040 => {
+                let ssw = read << 8 | fc;
+                let f = vec![sr, pc_hi, pc_lo, 0x7000 | fv, hi, lo, ssw];
+                ([f, vec![0; 3], vec![hi, lo]].concat(), 30)
+            }
+        };
+        frame.resize(len, 0);
+        frame
+    }
+
+    fn push(&mut self, bus: &mut impl Bus, s: Size, x: u32) -> Result<(), u8> {
+        self.addr_r[7] = self.addr_r[7].wrapping_sub(s.value() as u32);
+        self.write_mem(bus, self.addr_r[7], s, x)
+    }
+
+    // Runs the queued micro-ops, servicing their memory requests. A fault
+    // empties the queue and reports the exception vector to take.
+    fn run_microcode(&mut self, bus: &mut impl Bus) -> Result<(), u8> {
+        while let Some(mi) = self.instrs.pop_front() {
+            if let Err(v) = self.exec_microop(bus, mi) {
+                self.abort_microcode(bus);
+                return Err(v);
+            }
+        }
+        Ok(())
+    }
+
+    fn exec_microop(
+        &mut self,
+        bus: &mut impl Bus,
+        mi: MicroI,
+    ) -> Result<(), u8> {
+        match self.exec(mi) {
+            NextAction::Next => Ok(()),
+            NextAction::MemRequest(addr, s) => self
+                .read_mem(bus, addr, s, self.space(false))
+                .map(|x| self.write_reg(Reg::IOBuffer, x)),
+            NextAction::ProgramRequest(addr, s) => self
+                .read_mem(bus, addr, s, self.space(true))
+                .map(|x| self.write_reg(Reg::IOBuffer, x)),
+            NextAction::MemWrite(addr, x, s) => {
+                self.write_mem(bus, addr, s, x)
+            }
+            NextAction::Exception(v) => Err(v),
+            NextAction::ResetDevices => {
+                bus.reset_devices();
+                Ok(())
+            }
+            NextAction::BusLock(l) => {
+                bus.set_lock(l);
+                Ok(())
+            }
+        }
+    }
+
+    // Drops the rest of a faulting instruction. The bus is still locked if
+    // the micro-op releasing it was among those dropped.
+    fn abort_microcode(&mut self, bus: &mut impl Bus) {
+        if self.instrs.contains(&MicroI::Lock(false)) {
+            bus.set_lock(false);
+        }
+        self.instrs.clear();
+    }
+
+    // Runs a single micro-op, decoding the next instruction first when none
+    // are queued. Instruction boundaries see timers and interrupts as with
+    // `step`, without its tracing, statistics and history.
+    pub fn step_microop(&mut self, bus: &mut impl Bus) -> MicroStepOutcome {
+        if !matches!(self.state, State::Running) || self.held.is_some() {
+            self.step(bus);
+            return MicroStepOutcome::Completed;
+        }
+        let decoded = if self.instrs.is_empty() {
+            self.ir_pc = self.pc;
+            self.ir_words.clear();
+            self.fetch_word(bus).and_then(|op| {
+                self.ir = op;
+                self.decode_opcode(bus, op)?;
+                self.prefetch_next(bus);
+                Ok(())
+            })
+        } else {
+            Ok(())
+        };
+        let res = decoded.and_then(|()| match self.instrs.pop_front() {
+            Some(mi) => self.exec_microop(bus, mi),
+            None => Ok(()),
+        });
+        if let Some(e) = self.decode_error.take() {
+            self.pc = self.ir_pc;
+            self.instrs.clear();
+            return MicroStepOutcome::DecodeError(e);
+        }
+        if let Err(v) = res {
+            self.abort_microcode(bus);
+            self.exception(bus, v);
+        }
+        if !self.instrs.is_empty() {
+            return MicroStepOutcome::MidInstruction;
+        }
+        self.tick_timers();
+        self.service_pending(bus, false);
+        MicroStepOutcome::Completed
+    }
+
+    // Address space the CPU accesses in its current mode.
+    fn space(&self, program: bool) -> FunctionCode {
+        match (self.supervisor(), program) {
+            (false, false) => FunctionCode::UserData,
+            (false, true) => FunctionCode::UserProgram,
+            (true, false) => FunctionCode::SupervisorData,
+            (true, true) => FunctionCode::SupervisorProgram,
+        }
+    }
+
+    // Memory access as seen by the CPU: word and long accesses must be
+    // aligned, and faults are turned into their exception vector. Writes
+    // always go to data space.
+    fn read_mem(
+        &mut self,
+        bus: &mut impl Bus,
+        addr: u32,
+        s: Size,
+        fc: FunctionCode,
+    ) -> Result<u32, u8> {
+        self.fault = AccessFault {
+            addr,
+            write: false,
+            fc,
+        };
+        if !s.aligned(addr) && self.align_policy == AlignPolicy::Strict {
+            return Err(3);
+        }
+        self.read_bus(bus, addr, s, fc).map_err(|_| 2)
+    }
+
+    fn write_mem(
+        &mut self,
+        bus: &mut impl Bus,
+        addr: u32,
+        s: Size,
+        x: u32,
+    ) -> Result<(), u8> {
+        let fc = self.space(false);
+        self.fault = AccessFault {
+            addr,
+            write: true,
+            fc,
+        };
+        if !s.aligned(addr) && self.align_policy == AlignPolicy::Strict {
+            return Err(3);
+        }
+        self.write_bus(bus, addr, s, x, fc).map_err(|_| 2)?;
+        if let Some(watch) = &mut self.vector_watch {
+            // The table has 256 vectors of 4 bytes.
+            let first = addr.wrapping_sub(self.vbr);
+            let last = first.wrapping_add(s.value() as u32 - 1);
+            for off in [first, last] {
+                if off < 0x400 && (off == first || off / 4 != first / 4) {
+                    watch((off / 4) as u8);
+                    self.vector_written.get_or_insert((off / 4) as u8);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_bus(
+        &mut self,
+        bus: &mut impl Bus,
+        addr: u32,
+        s: Size,
+        fc: FunctionCode,
+    ) -> Result<u32, BusError> {
+        let (unit, n) = self.bus_width.split(s);
+        let mut x = 0;
+        for i in 0..n {
+            let a = addr.wrapping_add(i * unit.value() as u32);
+            self.cycles += bus.refresh_penalty(self.cycles) as u64;
+            // Devices may drive more lines than the cycle samples.
+            let v = bus.read(a, unit, fc)? & unit.mask();
+            self.bus_cycle(a, unit, v, false, fc);
+            x = x << (8 * unit.value()) | v;
+        }
+        Ok(x)
+    }
+
+    fn write_bus(
+        &mut self,
+        bus: &mut impl Bus,
+        addr: u32,
+        s: Size,
+        x: u32,
+        fc: FunctionCode,
+    ) -> Result<(), BusError> {
+        let (unit, n) = self.bus_width.split(s);
+        let bits = 8 * unit.value() as u32;
+        for i in 0..n {
+            let a = addr.wrapping_add(i * unit.value() as u32);
+            let v = (x >> (bits * (n - 1 - i))) & unit.mask();
+            if self.history_len != 0 {
+                let old = bus::peek(bus, a, unit);
+                if let (Some(undo), Ok(old)) = (self.history.back_mut(), old) {
+                    undo.writes.push((a, unit, old));
+                }
+            }
+            self.cycles += bus.refresh_penalty(self.cycles) as u64;
+            bus.write(a, unit, v, fc)?;
+            self.bus_cycle(a, unit, v, true, fc);
+        }
+        Ok(())
+    }
+
+    fn bus_cycle(
+        &mut self,
+        addr: u32,
+        size: Size,
+        value: u32,
+        write: bool,
+        fc: FunctionCode,
+    ) {
+        self.cycles += 4;
+        if let Some(trace) = &mut self.mem_trace {
+            trace(MemAccess {
+                addr,
+                size,
+                value,
+                write,
+                fc,
+            });
+        }
+    }
+
+    // Only touches the low `s` bits of `r`, like byte and word operations on
+    // data registers do.
+    fn write_reg_sized(&mut self, r: Reg, x: u32, s: Size) {
+        let m = s.mask();
+        let x = (self.read_reg(r) & !m) | (x & m);
+        self.write_reg(r, x);
+    }
+
+    fn add_instr(&mut self, mi: MicroI) {
+        self.instrs.push_back(mi);
+    }
+
+    // Micro-ops queued by decoding and not run yet.
+    pub fn pending_microops(&self) -> &VecDeque<MicroI> {
+        &self.instrs
+    }
+
+    // The pending micro-ops, one per line.
+    pub fn dump_pending(&self) -> String {
+        let lines = self.instrs.iter().map(|i| format!("{}\n", i));
+        lines.collect()
+    }
+
+    // Queues micro-ops of one's own after those pending. They run on the
+    // next step, ahead of the instruction it decodes.
+    pub fn inject_microops(&mut self, ops: impl IntoIterator<Item = MicroI>) {
+        self.instrs.extend(ops);
+    }
+
+    pub fn clear_microops(&mut self) {
+        self.instrs.clear();
+    }
+
+    // Loads the `s`-sized operand designated by `ea` into In0.
+    fn load_effaddr(&mut self, ea: EffAddr, s: Size) {
+        use Reg::*;
+        use MicroI::*;
+        match ea {
+            EffAddr::DataReg { r } => self.add_instr(Mov(In0, D(r as usize))),
+            EffAddr::AddrReg { r } => self.add_instr(Mov(In0, A(r as usize))),
+            EffAddr::Immediate { x } => self.add_instr(Set(In0, x)),
+            _ => {
+                self.load_effaddr_noderef(ea);
+                match ea {
+                    EffAddr::PCIndDisp { .. }
+                    | EffAddr::PCIndIdx { .. } => {
+                        self.add_instr(RequestProgram(In0, s))
+                    }
+                    _ => self.add_instr(RequestMem(In0, s)),
+                }
+                self.add_instr(Mov(In0, IOBuffer));
+                // The bus accounts for the extension words and the operand,
+                // what is left is spent computing the address.
+                let words = ext_words(&ea) + s.value().max(2) as u32 / 2;
+                let extra = ea_cycles(&ea, s).saturating_sub(4 * words);
+                self.cycles += extra as u64;
+            }
+        }
+    }
+
+    // Same as `load_effaddr`, with the operand sign-extended to 32 bits for
+    // operations done on the whole register.
+    fn load_effaddr_signed(&mut self, ea: EffAddr, s: Size) {
+        self.load_effaddr(ea, s);
+        self.add_instr(MicroI::Ext(Reg::In0, s));
+    }
+
+    // Memory indirect modes: the address is read from base + d, then offset
+    // by od. The index is added to the first address when preindexed, to the
+    // one read from memory otherwise. The result is left in In0.
+    fn emit_mem_indirect(
+        &mut self,
+        base: Reg,
+        d: i32,
+        idx: Option<Index>,
+        od: i32,
+        preindex: bool,
+    ) {
+        use MicroI::*;
+        use Reg::*;
+        self.add_instr(Mov(In0, base));
+        self.add_instr(addr_add(In0, Immediate(d)));
+        if preindex {
+            self.add_index(idx);
+        }
+        self.add_instr(RequestMem(In0, Size::Long));
+        self.add_instr(Mov(In0, IOBuffer));
+        if !preindex {
+            self.add_index(idx);
+        }
+        self.add_instr(addr_add(In0, Immediate(od)));
+    }
+
+    // Adds the scaled index register to In0, if not suppressed. A word index
+    // is sign-extended first, so that it can go below the base.
+    fn add_index(&mut self, idx: Option<Index>) {
+        use MicroI::*;
+        use Reg::*;
+        let Some(idx) = idx else { return };
+        self.add_instr(Mov(In1, idx.r));
+        if !idx.long {
+            self.add_instr(Ext(In1, Size::Word));
+        }
+        self.add_instr(Scale(In1, idx.scale));
+        self.add_instr(addr_add(In0, In1));
+    }
+
+    // Leaves the address designated by `ea` in In0 instead of the operand,
+    // for stores and read-modify-write accesses. Register direct modes have
+    // no address.
+    fn load_effaddr_noderef(&mut self, ea: EffAddr) {
+        use MicroI::*;
+        use Reg::*;
+        match ea {
+            EffAddr::DataReg { .. } | EffAddr::AddrReg { .. } => {
+                unreachable!()
+            }
+            EffAddr::Addr { r } => self.add_instr(Mov(In0, A(r as usize))),
+            EffAddr::PostInc { r, s } => {
+                let a = A(r as usize);
+                self.add_instr(Mov(In0, a));
+                self.add_instr(addr_add(a, Immediate(step(r, s))));
+            }
+            EffAddr::PreDec { r, s } => {
+                let a = A(r as usize);
+                self.add_instr(addr_add(a, Immediate(-step(r, s))));
+                self.add_instr(Mov(In0, a));
+            }
+            EffAddr::AddrDisp { r, d } => {
+                self.add_instr(Mov(In0, A(r as usize)));
+                self.add_instr(addr_add(In0, Immediate(d as i32)));
+            }
+            EffAddr::AddrIdx { r, idx, d } => {
+                self.add_instr(Mov(In0, base_reg(r)));
+                self.add_instr(addr_add(In0, Immediate(d)));
+                self.add_index(idx);
+            }
+            EffAddr::AddrIndPostIdx { r, d, idx, od } => {
+                self.emit_mem_indirect(base_reg(r), d, idx, od, false)
+            }
+            EffAddr::AddrIndPreIdx { r, d, idx, od } => {
+                self.emit_mem_indirect(base_reg(r), d, idx, od, true)
+            }
+            EffAddr::PCIndDisp { base, d } => {
+                self.add_instr(Set(In0, base.wrapping_add(d as u32)))
+            }
+            EffAddr::PCIndIdx { base, d, idx } => {
+                let base = base.unwrap_or(0);
+                self.add_instr(Set(In0, base.wrapping_add(d as u32)));
+                self.add_index(idx);
+            }
+            EffAddr::PCIndPostIdx { base, d, idx, od } => {
+                let base = Immediate(base.unwrap_or(0) as i32);
+                self.emit_mem_indirect(base, d, idx, od, false)
+            }
+            EffAddr::PCIndPreIdx { base, d, idx, od } => {
+                let base = Immediate(base.unwrap_or(0) as i32);
+                self.emit_mem_indirect(base, d, idx, od, true)
+            }
+            EffAddr::AbsShort { addr } => {
+                // Absolute short addresses are sign-extended: $8000 is the
+                // top of the address space, $FFFF8000.
+                self.add_instr(Set(In0, addr as i32 as u32))
+            }
+            EffAddr::AbsLong { hi, lo } => {
+                self.add_instr(Set(In0, (hi as u32) << 16 | lo as u32))
+            }
+            // An immediate has no address to write to.
+            EffAddr::Immediate { .. } => self.add_instr(TrapIf(0, 4)),
+        }
+    }
+}
+
+// Serves reads of the instruction `words` placed at `base`, and passes any
+// other access through to `bus`.
+struct WordStream<'a, B> {
+    base: u32,
+    words: &'a [u16],
+    bus: &'a mut B,
+}
+
+impl<B: Bus> Bus for WordStream<'_, B> {
+    fn read(
+        &mut self,
+        addr: u32,
+        s: Size,
+        fc: FunctionCode,
+    ) -> Result<u32, BusError> {
+        let off = addr.wrapping_sub(self.base) as usize;
+        let n = s.value() as usize;
+        if off + n > self.words.len() * 2 {
+            return self.bus.read(addr, s, fc);
+        }
+        Ok((off..off + n).fold(0, |x, i| {
+            let w = self.words[i / 2] as u32;
+            x << 8 | if i % 2 == 0 { w >> 8 } else { w & 0xff }
+        }))
+    }
+
+    fn write(
+        &mut self,
+        addr: u32,
+        s: Size,
+        x: u32,
+        fc: FunctionCode,
+    ) -> Result<(), BusError> {
+        self.bus.write(addr, s, x, fc)
+    }
+
+    fn set_lock(&mut self, locked: bool) {
+        self.bus.set_lock(locked)
+    }
+
+    fn reset_devices(&mut self) {
+        self.bus.reset_devices()
+    }
+
+    fn refresh_penalty(&mut self, cycle: u64) -> u32 {
+        self.bus.refresh_penalty(cycle)
+    }
+}
+
+// 68000 effective address calculation times, reading the extension words
+// and the operand included.
+fn ea_cycles(ea: &EffAddr, s: Size) -> u32 {
+    let (word, long) = match ea {
+        EffAddr::DataReg { .. } | EffAddr::AddrReg { .. } => (0, 0),
+        EffAddr::Addr { .. } | EffAddr::PostInc { .. } => (4, 8),
+        EffAddr::PreDec { .. } => (6, 10),
+        EffAddr::AddrDisp { .. } | EffAddr::PCIndDisp { .. } => (8, 12),
+        EffAddr::AddrIdx { .. } | EffAddr::PCIndIdx { .. } => (10, 14),
+        EffAddr::AbsShort { .. } => (8, 12),
+        EffAddr::AbsLong { .. } => (12, 16),
+        EffAddr::Immediate { .. } => (4, 8),
+        // 68020 modes, which the 68000 tables do not cover.
+        EffAddr::AddrIndPostIdx { .. }
+        | EffAddr::AddrIndPreIdx { .. }
+        | EffAddr::PCIndPostIdx { .. }
+        | EffAddr::PCIndPreIdx { .. } => (0, 0),
+    };
+    if s == Size::Long {
+        long
+    } else {
+        word
+    }
+}
+
+// Extension words fetched while decoding `ea`. Immediates are read as the
+// operand instead.
+fn ext_words(ea: &EffAddr) -> u32 {
+    match ea {
+        EffAddr::AddrDisp { .. }
+        | EffAddr::AddrIdx { .. }
+        | EffAddr::PCIndDisp { .. }
+        | EffAddr::PCIndIdx { .. }
+        | EffAddr::AbsShort { .. } => 1,
+        EffAddr::AbsLong { .. } => 2,
+        _ => 0,
+    }
+}
+
+// Base register of the An indexed modes, a suppressed one reading as 0.
+fn base_reg(r: Option<u8>) -> Reg {
+    r.map_or(Reg::Immediate(0), |r| Reg::A(r as usize))
+}
+
+// How much (An)+ and -(An) move An by. A7 stays word aligned, so bytes move
+// it by two.
+fn step(r: u8, s: Size) -> i32 {
+    match (r, s) {
+        (7, Size::Byte) => 2,
+        _ => s.value(),
+    }
+}
+
+// Index register of the indexed modes, used whole or as a word, and
+// shifted left by `scale`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Index {
+    pub r: Reg,
+    pub long: bool,
+    pub scale: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EffAddr {
+    DataReg { r: u8 }, // 000
+    AddrReg { r: u8 }, // 001
+    Addr { r: u8 }, // 010
+    PostInc { r: u8, s: Size }, // 011
+    PreDec { r: u8, s: Size }, // 100
+    AddrDisp { r: u8, d: i16 }, // 101
+    // 110. The full format extension word may suppress the base register
+    // and the index, leaving them None.
+    AddrIdx {
+        r: Option<u8>,
+        idx: Option<Index>,
+        d: i32,
+    },
+    // 110
+    AddrIndPostIdx {
+        r: Option<u8>,
+        d: i32,
+        idx: Option<Index>,
+        od: i32,
+    },
+    // 110
+    AddrIndPreIdx {
+        r: Option<u8>,
+        d: i32,
+        idx: Option<Index>,
+        od: i32,
+    },
+    // 111. The displacement is relative to `base`, the address of the first
+    // extension word as captured when decoding, None when suppressed.
+    PCIndDisp {
+        base: u32,
+        d: i32,
+    },
+    PCIndIdx {
+        base: Option<u32>,
+        d: i32,
+        idx: Option<Index>,
+    },
+    PCIndPostIdx {
+        base: Option<u32>,
+        d: i32,
+        idx: Option<Index>,
+        od: i32,
+    },
+    PCIndPreIdx {
+        base: Option<u32>,
+        d: i32,
+        idx: Option<Index>,
+        od: i32,
+    },
+    AbsShort { addr: i16 },
+    AbsLong { hi: u16, lo: u16 },
+    Immediate { x: u32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Size {
+    Byte,
+    Word,
+    Long,
+}
+
+impl Size {
+    fn value(self) -> i32 {
+        match self {
+            Size::Byte => 1,
+            Size::Word => 2,
+            Size::Long => 4,
+        }
+    }
+
+    fn aligned(self, addr: u32) -> bool {
+        matches!(self, Size::Byte) || addr & 1 == 0
+    }
+
+    fn msb(self) -> u32 {
+        (self.mask() >> 1) + 1
+    }
+
+    fn mask(self) -> u32 {
+        match self {
+            Size::Byte => 0xff,
+            Size::Word => 0xffff,
+            Size::Long => 0xffff_ffff,
+        }
+    }
+}
+
+// Builds a CPU and resets it from the vector table already on `bus`, ready to
+// be stepped.
+pub fn boot(bus: &mut impl Bus) -> M68K {
+    let mut cpu = M68K::new();
+    cpu.reset(bus);
+    assert!(
+        matches!(cpu.state, State::Running),
+        "reset vectors are unreadable"
+    );
+    for v in [cpu.addr_r[7], cpu.pc] {
+        assert!(v != 0 && v != 0xffff_ffff, "reset vectors not programmed");
+    }
+    cpu
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::{poke, Ram};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // A CPU of `variant` in supervisor mode, about to run `words` at $100
+    // out of 64K of RAM.
+    fn setup(variant: Variant, words: &[u16]) -> (M68K, Ram) {
+        let mut ram = Ram::new(0, 0x10000);
+        for (i, &w) in words.iter().enumerate() {
+            let addr = 0x100 + 2 * i as u32;
+            poke(&mut ram, addr, Size::Word, w as u32).unwrap();
+        }
+        let mut cpu = M68K::with_state(0x100, 0x2700, 0x8000);
+        cpu.variant = variant;
+        cpu.ir_pc = 0x100;
+        (cpu, ram)
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    enum Event {
+        Read(u32, Size, FunctionCode),
+        Write(u32, Size, FunctionCode),
+        Lock(bool),
+        Reset,
+    }
+
+    // Passes accesses through to `ram`, logging them along with the bus
+    // lock. Data reads fail with `data_reads_fail`, and every bus cycle
+    // takes `wait_states` more. With `lines_high`, reads drive the data lines
+    // above the access size high.
+    struct Recorder {
+        ram: Ram,
+        log: Vec<Event>,
+        data_reads_fail: bool,
+        wait_states: u32,
+        lines_high: bool,
+    }
+
+    impl Recorder {
+        fn new(ram: Ram) -> Recorder {
+            Recorder {
+                ram,
+                log: Vec::new(),
+                data_reads_fail: false,
+                wait_states: 0,
+                lines_high: false,
+            }
+        }
+    }
+
+    impl Bus for Recorder {
+        fn read(
+            &mut self,
+            addr: u32,
+            s: Size,
+            fc: FunctionCode,
+        ) -> Result<u32, BusError> {
+            self.log.push(Event::Read(addr, s, fc));
+            let data = matches!(
+                fc,
+                FunctionCode::UserData | FunctionCode::SupervisorData
+            );
+            if data && self.data_reads_fail {
+                return Err(BusError);
+            }
+            let x = self.ram.read(addr, s, fc)?;
+            Ok(if self.lines_high { x | !s.mask() } else { x })
+        }
+
+        fn write(
+            &mut self,
+            addr: u32,
+            s: Size,
+            x: u32,
+            fc: FunctionCode,
+        ) -> Result<(), BusError> {
+            self.log.push(Event::Write(addr, s, fc));
+            self.ram.write(addr, s, x, fc)
+        }
+
+        fn set_lock(&mut self, locked: bool) {
+            self.log.push(Event::Lock(locked));
+        }
+
+        fn reset_devices(&mut self) {
+            self.log.push(Event::Reset);
+        }
+
+        fn refresh_penalty(&mut self, _cycle: u64) -> u32 {
+            self.wait_states
+        }
+    }
+
+    fn d0_word() -> Option<Index> {
+        Some(Index {
+            r: Reg::D(0),
+            long: false,
+            scale: 0,
+        })
+    }
+
+    #[test]
+    fn full_format_extension_word() {
+        // ([$10,a0,d0.w]): word base displacement, preindexed, no od.
+        let (mut cpu, mut ram) = setup(Variant::M68020, &[0x0121, 0x0010]);
+        let ea = cpu.decode_effaddr(&mut ram, 0o60, Size::Word);
+        let expected = EffAddr::AddrIndPreIdx {
+            r: Some(0),
+            d: 0x10,
+            idx: d0_word(),
+            od: 0,
+        };
+        assert_eq!(ea, Ok(expected));
+        assert_eq!(cpu.pc, 0x104);
+
+        // ($12345): base and index suppressed, long displacement.
+        let words = [0x01f0, 0x0001, 0x2345];
+        let (mut cpu, mut ram) = setup(Variant::M68020, &words);
+        let ea = cpu.decode_effaddr(&mut ram, 0o60, Size::Word);
+        let expected = EffAddr::AddrIdx {
+            r: None,
+            idx: None,
+            d: 0x12345,
+        };
+        assert_eq!(ea, Ok(expected));
+    }
+
+    #[test]
+    fn full_format_is_illegal_before_the_68020() {
+        for variant in [Variant::M68000, Variant::M68010] {
+            let (mut cpu, mut ram) = setup(variant, &[0x0121, 0x0010]);
+            let ea = cpu.decode_effaddr(&mut ram, 0o60, Size::Word);
+            assert_eq!(ea, Err(4));
+        }
+    }
+
+    #[test]
+    fn preindexed_modes_only_differ_in_their_base() {
+        let mut cpu = M68K::new();
+        cpu.load_effaddr_noderef(EffAddr::AddrIndPreIdx {
+            r: Some(0),
+            d: 4,
+            idx: d0_word(),
+            od: 8,
+        });
+        let an: Vec<MicroI> = cpu.instrs.drain(..).collect();
+        cpu.load_effaddr_noderef(EffAddr::PCIndPreIdx {
+            base: Some(0x1000),
+            d: 4,
+            idx: d0_word(),
+            od: 8,
+        });
+        let pc: Vec<MicroI> = cpu.instrs.drain(..).collect();
+        assert_eq!(an[0], MicroI::Mov(Reg::In0, Reg::A(0)));
+        assert_eq!(pc[0], MicroI::Mov(Reg::In0, Reg::Immediate(0x1000)));
+        assert_eq!(an[1..], pc[1..]);
+        // The index is added before the indirection.
+        let read = MicroI::RequestMem(Reg::In0, Size::Long);
+        let index = MicroI::Mov(Reg::In1, Reg::D(0));
+        let at = |op: &MicroI| an.iter().position(|i| i == op).unwrap();
+        assert!(at(&index) < at(&read));
+    }
+
+    // Loads the word `ea` designates with A0 = $2004 and D1 = 2, RAM
+    // holding $1111, $2222, ... $8888 from $2000 and the long $2004 at
+    // $3000. Returns the CPU and what ended up in In0.
+    fn load_word(ea: EffAddr) -> (M68K, u32) {
+        let (mut cpu, mut ram) = setup(Variant::M68020, &[]);
+        for i in 0..8 {
+            let x = 0x1111 * (i + 1);
+            poke(&mut ram, 0x2000 + 2 * i, Size::Word, x).unwrap();
+        }
+        poke(&mut ram, 0x3000, Size::Long, 0x2004).unwrap();
+        cpu.addr_r[0] = 0x2004;
+        cpu.data_r[1] = 2;
+        cpu.load_effaddr(ea, Size::Word);
+        cpu.run_microcode(&mut ram).unwrap();
+        let x = cpu.read_reg(Reg::In0);
+        (cpu, x)
+    }
+
+    #[test]
+    fn load_effaddr_reads_each_mode() {
+        let d1 = |scale| {
+            Some(Index {
+                r: Reg::D(1),
+                long: false,
+                scale,
+            })
+        };
+        let cases = [
+            (EffAddr::DataReg { r: 1 }, 2),
+            (EffAddr::AddrReg { r: 0 }, 0x2004),
+            (EffAddr::Addr { r: 0 }, 0x3333),
+            (EffAddr::AddrDisp { r: 0, d: -4 }, 0x1111),
+            (
+                EffAddr::AddrIdx {
+                    r: Some(0),
+                    idx: d1(0),
+                    d: 2,
+                },
+                0x5555,
+            ),
+            (
+                EffAddr::AddrIdx {
+                    r: Some(0),
+                    idx: d1(1),
+                    d: -2,
+                },
+                0x4444,
+            ),
+            // [$3000] + 4
+            (
+                EffAddr::AddrIndPreIdx {
+                    r: Some(0),
+                    d: 0xffa,
+                    idx: d1(0),
+                    od: 4,
+                },
+                0x5555,
+            ),
+            (
+                EffAddr::AddrIndPreIdx {
+                    r: None,
+                    d: 0x2ffe,
+                    idx: d1(0),
+                    od: 0,
+                },
+                0x3333,
+            ),
+            // [$3000] + d1 + 0
+            (
+                EffAddr::AddrIndPostIdx {
+                    r: Some(0),
+                    d: 0xffc,
+                    idx: d1(0),
+                    od: 0,
+                },
+                0x4444,
+            ),
+            (EffAddr::PCIndDisp { base: 0x1ff0, d: 0x10 }, 0x1111),
+            (
+                EffAddr::PCIndIdx {
+                    base: Some(0x2000),
+                    d: 0,
+                    idx: d1(0),
+                },
+                0x2222,
+            ),
+            (
+                EffAddr::PCIndPreIdx {
+                    base: Some(0x2ff0),
+                    d: 0xe,
+                    idx: d1(0),
+                    od: 0,
+                },
+                0x3333,
+            ),
+            (
+                EffAddr::PCIndPostIdx {
+                    base: Some(0x3000),
+                    d: 0,
+                    idx: d1(0),
+                    od: 2,
+                },
+                0x5555,
+            ),
+            (EffAddr::AbsShort { addr: 0x2000 }, 0x1111),
+            (EffAddr::AbsLong { hi: 0, lo: 0x200e }, 0x8888),
+            (EffAddr::Immediate { x: 0x2222 }, 0x2222),
+        ];
+        for (ea, expected) in cases {
+            assert_eq!(load_word(ea).1, expected, "{:?}", ea);
+        }
+    }
+
+    #[test]
+    fn load_effaddr_updates_an() {
+        let (cpu, x) = load_word(EffAddr::PostInc { r: 0, s: Size::Word });
+        assert_eq!((x, cpu.addr_r[0]), (0x3333, 0x2006));
+        let (cpu, x) = load_word(EffAddr::PreDec { r: 0, s: Size::Word });
+        assert_eq!((x, cpu.addr_r[0]), (0x2222, 0x2002));
+    }
+
+    #[test]
+    fn effaddr_equality() {
+        let a = EffAddr::AddrDisp { r: 2, d: -8 };
+        assert_eq!(a, EffAddr::AddrDisp { r: 2, d: -8 });
+        assert_ne!(a, EffAddr::AddrDisp { r: 2, d: 8 });
+        assert_ne!(a, EffAddr::AddrDisp { r: 3, d: -8 });
+    }
+
+    #[test]
+    fn fetch_trace_sees_immediates() {
+        // move.l #$12345678,d0
+        let words = [0x203c, 0x1234, 0x5678];
+        let (mut cpu, mut ram) = setup(Variant::M68000, &words);
+        let fetches = Rc::new(RefCell::new(Vec::new()));
+        let log = fetches.clone();
+        cpu.fetch_trace =
+            Some(Box::new(move |a, x| log.borrow_mut().push((a, x))));
+        cpu.step(&mut ram);
+        assert_eq!(cpu.data_r[0], 0x12345678);
+        let expected = [(0x100, 0x203c), (0x102, 0x1234), (0x104, 0x5678)];
+        assert_eq!(*fetches.borrow(), expected);
+    }
+
+    #[test]
+    fn prefetched_word_is_stale() {
+        // move.w d1,($104).w overwrites the moveq #1,d0 that follows with
+        // moveq #2,d0, which was already fetched.
+        let words = [0x31c1, 0x0104, 0x7001];
+        for flush in [false, true] {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &words);
+            cpu.data_r[1] = 0x7002;
+            cpu.step(&mut ram);
+            if flush {
+                cpu.flush_prefetch();
+            }
+            cpu.step(&mut ram);
+            assert_eq!(cpu.data_r[0], if flush { 2 } else { 1 });
+        }
+    }
+
+    #[test]
+    fn retire_trace_decodes_the_fetched_words() {
+        // move.w d1,($100).w overwrites itself with a NOP.
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x31c1, 0x0100]);
+        cpu.data_r[1] = 0x4e71;
+        let retired = Rc::new(RefCell::new(Vec::new()));
+        let log = retired.clone();
+        cpu.retire_trace =
+            Some(Box::new(move |d| log.borrow_mut().push(d.clone())));
+        cpu.step(&mut ram);
+        let retired = retired.borrow();
+        assert_eq!(retired.len(), 1);
+        assert_eq!(retired[0].render(None), "move.w d1,$100.w");
+    }
+
+    #[test]
+    fn long_branches_need_a_68020() {
+        let bra = [0x60ff, 0x0000, 0x0100];
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[]);
+        assert_eq!(cpu.execute_opcode(&bra, &mut ram), Err(4));
+        let (mut cpu, mut ram) = setup(Variant::M68020, &[]);
+        assert_eq!(cpu.execute_opcode(&bra, &mut ram), Ok(()));
+        assert_eq!(cpu.pc, 0x202);
+    }
+
+    // Everything stepping may change, memory included.
+    type FullState = (CpuState, [u32; 6], [u8; 3], u64, Vec<(u64, bool)>);
+
+    fn full_state(cpu: &M68K, ram: &Ram) -> (FullState, Vec<u8>) {
+        let timers = cpu.timers.iter().map(|t| (t.deadline, t.raised));
+        let state = (
+            cpu.snapshot(),
+            [cpu.usp, cpu.ssp, cpu.msp, cpu.vbr, cpu.cacr, cpu.caar],
+            [cpu.sfc, cpu.dfc, cpu.irq_lines],
+            cpu.cycles,
+            timers.collect(),
+        );
+        (state, ram.bytes.clone())
+    }
+
+    #[test]
+    fn stepping_back_restores_everything() {
+        // moveq #5,d0, then an interrupt to $200 which pushes d0, moves VBR,
+        // and writes memory.
+        let (mut cpu, mut ram) = setup(Variant::M68010, &[0x7005]);
+        let handler = [
+            0x2f00, // move.l d0,-(a7)
+            0x4e7b, 0x1801, // movec d1,vbr
+            0x5297, // addq.l #1,(a7)
+            0x31fc, 0x1234, 0x2000, // move.w #$1234,($2000).w
+            0x4e71, // nop
+        ];
+        for (i, &w) in handler.iter().enumerate() {
+            poke(&mut ram, 0x200 + 2 * i as u32, Size::Word, w).unwrap();
+        }
+        poke(&mut ram, 26 * 4, Size::Long, 0x200).unwrap();
+        cpu.set_sr(0x2000);
+        cpu.data_r[1] = 0x400;
+        cpu.set_irq(2, true);
+        cpu.add_periodic(12, 1, 64);
+        cpu.record_history(16);
+        let before = full_state(&cpu, &ram);
+        for _ in 0..6 {
+            cpu.step(&mut ram);
+        }
+        assert_eq!(cpu.vbr, 0x400);
+        assert_eq!(cpu.irq_lines, 0);
+        assert!(full_state(&cpu, &ram) != before);
+        for _ in 0..6 {
+            assert!(cpu.step_back(&mut ram));
+        }
+        assert!(!cpu.step_back(&mut ram));
+        assert!(full_state(&cpu, &ram) == before);
+    }
+
+    #[test]
+    fn step_over_returns_past_the_call() {
+        // The subroutine goes through an RTE before its RTS.
+        let program = [
+            0x6104, // $100: bsr.s $106
+            0x4e71, // $102: nop
+            0x4e71, // $104: nop
+            0x2f3c, 0x0000, 0x0110, // $106: move.l #$110,-(a7)
+            0x40e7, // move sr,-(a7)
+            0x4e73, // rte to $110
+            0x4e75, // $110: rts
+        ];
+        let (mut cpu, mut ram) = setup(Variant::M68000, &program);
+        cpu.step_over(&mut ram, 10);
+        assert_eq!((cpu.pc, cpu.addr_r[7]), (0x102, 0x8000));
+    }
+
+    #[test]
+    fn too_many_extension_words() {
+        // move.l #$12345678,d0 has two.
+        let words = [0x203c, 0x1234, 0x5678];
+        let (mut cpu, mut ram) = setup(Variant::M68000, &words);
+        cpu.set_max_ext_words(1);
+        let error = DecodeError::TooManyExtensionWords;
+        assert_eq!(cpu.step(&mut ram), StepOutcome::DecodeError(error));
+        assert_eq!((cpu.pc, cpu.data_r[0]), (0x100, 0));
+        cpu.set_max_ext_words(2);
+        assert_eq!(cpu.step(&mut ram), StepOutcome::Executed);
+        assert_eq!(cpu.data_r[0], 0x12345678);
+    }
+
+    #[test]
+    fn misaligned_move16_records_the_fault() {
+        // move16 (a0)+,(a1)+
+        let move16 = [0xf620, 0x9000];
+        for (a0, a1, addr, write) in [
+            (0x1008, 0x2000, 0x1008, false),
+            (0x1000, 0x2004, 0x2004, true),
+        ] {
+            let (mut cpu, mut ram) = setup(Variant::M68040, &[]);
+            cpu.addr_r[0] = a0;
+            cpu.addr_r[1] = a1;
+            assert_eq!(cpu.execute_opcode(&move16, &mut ram), Err(3));
+            assert_eq!((cpu.fault.addr, cpu.fault.write), (addr, write));
+            assert_eq!(cpu.fault.fc, FunctionCode::SupervisorData);
+        }
+    }
+
+    #[test]
+    fn m68020_special_status_word() {
+        // move.w ($2000000).l,d0 with only 64K of RAM: a data fault.
+        let words = [0x3039, 0x0200, 0x0000];
+        let (mut cpu, mut ram) = setup(Variant::M68020, &words);
+        cpu.step(&mut ram);
+        let ssw = bus::peek(&mut ram, 0x8000 - 32 + 10, Size::Word).unwrap();
+        assert_eq!(ssw, 0x0100 | 0x40 | 5);
+        // Running from past the end of RAM: the opcode fetch faults.
+        let (mut cpu, mut ram) = setup(Variant::M68020, &[]);
+        cpu.pc = 0x20000;
+        cpu.step(&mut ram);
+        let ssw = bus::peek(&mut ram, 0x8000 - 32 + 10, Size::Word).unwrap();
+        assert_eq!(ssw, 0xc000 | 0x40 | 6);
+    }
+
+    // What reset leaves in the CPU, the cycle count aside.
+    fn internal_state(cpu: &M68K) -> impl PartialEq + std::fmt::Debug {
+        (
+            full_state_regs(cpu),
+            (cpu.ir, cpu.ir_pc, cpu.fault, cpu.ir_words.clone()),
+            (cpu.intern_r, cpu.instrs.clone(), cpu.prefetch),
+            (cpu.decode_error, matches!(cpu.state, State::Running)),
+            cpu.held,
+        )
+    }
+
+    fn full_state_regs(cpu: &M68K) -> (CpuState, [u32; 6], [u8; 2]) {
+        let sp = [cpu.usp, cpu.ssp, cpu.msp, cpu.vbr, cpu.cacr, cpu.caar];
+        (cpu.snapshot(), sp, [cpu.sfc, cpu.dfc])
+    }
+
+    #[test]
+    fn reset_clears_internal_state() {
+        let vectors = [0x0000, 0x8000, 0x0000, 0x0100];
+        let mut ram = Ram::new(0, 0x10000);
+        for (i, &w) in vectors.iter().enumerate() {
+            poke(&mut ram, 2 * i as u32, Size::Word, w).unwrap();
+        }
+        let mut fresh = M68K::new();
+        fresh.variant = Variant::M68020;
+        fresh.reset(&mut ram);
+        // Run into a bus error with the control registers set.
+        let (mut used, _) = setup(Variant::M68020, &[]);
+        used.caar = 0x1234;
+        used.cacr = 1;
+        used.vbr = 0x400;
+        used.pc = 0x20000;
+        used.step(&mut ram);
+        used.instrs.push_back(MicroI::StopCpu);
+        used.reset(&mut ram);
+        assert_eq!(internal_state(&used), internal_state(&fresh));
+    }
+
+    #[test]
+    fn unpk_to_memory_keeps_the_flags() {
+        // unpk -(a0),-(a1),#$3030
+        let (mut cpu, mut ram) = setup(Variant::M68020, &[0x8388, 0x3030]);
+        cpu.addr_r[0] = 0x201;
+        cpu.addr_r[1] = 0x302;
+        cpu.sr = 0x271f;
+        poke(&mut ram, 0x200, Size::Byte, 0x47).unwrap();
+        cpu.step(&mut ram);
+        let unpacked = bus::peek(&mut ram, 0x300, Size::Word).unwrap();
+        assert_eq!((unpacked, cpu.addr_r[1]), (0x3437, 0x300));
+        assert_eq!(cpu.sr, 0x271f);
+    }
+
+    #[test]
+    fn level_7_is_not_masked() {
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x4e71]);
+        poke(&mut ram, 31 * 4, Size::Long, 0x300).unwrap();
+        cpu.set_irq(7, true);
+        cpu.step(&mut ram);
+        assert_eq!((cpu.pc, cpu.sr), (0x300, 0x2700));
+        // The stacked SR shows the CPU was already at level 7.
+        let stacked = bus::peek(&mut ram, cpu.addr_r[7], Size::Word);
+        assert_eq!(stacked.unwrap(), 0x2700);
+    }
+
+    #[test]
+    fn byte_wide_bus_splits_words() {
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[]);
+        poke(&mut ram, 0x200, Size::Word, 0x1234).unwrap();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let sink = log.clone();
+        cpu.mem_trace = Some(Box::new(move |m: MemAccess| {
+            sink.borrow_mut().push((m.addr, m.size, m.value));
+        }));
+        cpu.set_bus_width(BusWidth::Bits8);
+        let (fc, cycles) = (FunctionCode::SupervisorData, cpu.cycles);
+        let x = cpu.read_bus(&mut ram, 0x200, Size::Word, fc).unwrap();
+        assert_eq!((x, cpu.cycles - cycles), (0x1234, 8));
+        let accesses = [(0x200, Size::Byte, 0x12), (0x201, Size::Byte, 0x34)];
+        assert_eq!(*log.borrow(), accesses);
+    }
+
+    #[test]
+    fn bit_field_across_a_byte_boundary() {
+        // bfextu (a0){6:5},d1 then bfins d2,(a0){6:5}
+        let words = [0xe9d0, 0x1185, 0xefd0, 0x2185];
+        let (mut cpu, mut ram) = setup(Variant::M68020, &words);
+        cpu.addr_r[0] = 0x200;
+        cpu.data_r[2] = 0x15;
+        poke(&mut ram, 0x200, Size::Word, 0x03c0).unwrap();
+        cpu.step(&mut ram);
+        assert_eq!(cpu.data_r[1], 0x1e);
+        assert_eq!(cpu.sr & 0xf, 0x8);
+        cpu.step(&mut ram);
+        let x = bus::peek(&mut ram, 0x200, Size::Word).unwrap();
+        assert_eq!(x, 0x02a0);
+    }
+
+    #[test]
+    fn cas_writes_du_or_loads_dc() {
+        // cas.w d0,d1,(a0): the operand before and after, and Dc after.
+        for (old, new, d0) in
+            [(0x1234, 0x5678, 0x1234), (0x1111, 0x1111, 0x1111)]
+        {
+            let (mut cpu, mut ram) = setup(Variant::M68020, &[0x0cd0, 0x0040]);
+            cpu.addr_r[0] = 0x200;
+            cpu.data_r[0] = 0xffff_1234;
+            cpu.data_r[1] = 0x5678;
+            poke(&mut ram, 0x200, Size::Word, old).unwrap();
+            cpu.step(&mut ram);
+            let x = bus::peek(&mut ram, 0x200, Size::Word).unwrap();
+            assert_eq!((x, cpu.data_r[0]), (new, 0xffff_0000 | d0));
+            assert_eq!(cpu.sr & 4 != 0, old == 0x1234);
+        }
+    }
+
+    #[test]
+    fn lenient_policy_lets_odd_longs_through() {
+        // move.l (a0),d0
+        for policy in [AlignPolicy::Lenient, AlignPolicy::Strict] {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &[0x2010]);
+            cpu.set_align_policy(policy);
+            cpu.addr_r[0] = 0x201;
+            poke(&mut ram, 0x201, Size::Long, 0x12345678).unwrap();
+            poke(&mut ram, 3 * 4, Size::Long, 0x300).unwrap();
+            cpu.step(&mut ram);
+            if policy == AlignPolicy::Lenient {
+                assert_eq!((cpu.data_r[0], cpu.pc), (0x12345678, 0x102));
+            } else {
+                assert_eq!((cpu.data_r[0], cpu.pc), (0, 0x300));
+            }
+        }
+    }
+
+    #[test]
+    fn power_on_pattern_fills_the_registers() {
+        let mut ram = Ram::new(0, 0x1000);
+        poke(&mut ram, 0, Size::Long, 0x800).unwrap();
+        poke(&mut ram, 4, Size::Long, 0x100).unwrap();
+        let mut cpu = M68K::new();
+        cpu.set_power_on_fill(PowerOnFill::Pattern(0xdeadbeef));
+        cpu.reset(&mut ram);
+        assert_eq!(cpu.data_r, [0xdeadbeef; 8]);
+        assert_eq!(cpu.addr_r[..7], [0xdeadbeef; 7]);
+        assert_eq!((cpu.usp, cpu.msp), (0xdeadbeef, 0xdeadbeef));
+        // The stack pointer and PC still come from the vectors.
+        assert_eq!((cpu.addr_r[7], cpu.pc), (0x800, 0x100));
+    }
+
+    #[test]
+    fn example_rom_prints_and_halts() {
+        let rom = include_bytes!("examples/hello.bin");
+        let mut sys = system::System::new(rom);
+        let mut cpu = boot(&mut sys);
+        let outcome = (0..1000)
+            .map(|_| cpu.step(&mut sys))
+            .find(|&o| o != StepOutcome::Executed);
+        assert_eq!(outcome, Some(StepOutcome::DoubleFault));
+        assert_eq!(sys.duart.tx[0], b"hello, world\n");
+    }
+
+    #[test]
+    fn word_and_long_index_registers() {
+        // lea 4(a0,d0.w),a1 and lea 4(a0,d0.l),a1
+        for (ext, ea) in [(0x0004, 0x1002), (0x0804, 0x21002)] {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &[0x43f0, ext]);
+            cpu.addr_r[0] = 0x1000;
+            cpu.data_r[0] = 0x0001_fffe;
+            cpu.step(&mut ram);
+            assert_eq!(cpu.addr_r[1], ea);
+        }
+    }
+
+    #[test]
+    fn cmpa_sign_extends_word_sources() {
+        // cmpa.w #$8000,a0
+        for x in [0, 0x10] {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &[0xb0fc, 0x8000]);
+            cpu.addr_r[0] = 0xffff_8000;
+            cpu.sr = 0x2700 | x;
+            cpu.step(&mut ram);
+            assert_eq!(cpu.sr, 0x2704 | x);
+        }
+    }
+
+    #[test]
+    fn odd_stack_pointer_double_faults() {
+        // illegal, whose frame cannot be pushed at an odd address
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x4afc]);
+        cpu.addr_r[7] = 0x7fff;
+        assert_eq!(cpu.step(&mut ram), StepOutcome::DoubleFault);
+        assert!(matches!(cpu.state, State::Halted));
+        // Nothing was written below the stack pointer.
+        assert!(ram.bytes[0x7f00..0x8000].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn exception_break_stops_before_stacking() {
+        // A garbage opcode, then the handler.
+        let (mut cpu, mut ram) = setup(Variant::M68010, &[0x4afa]);
+        poke(&mut ram, 4 * 4, Size::Long, 0x300).unwrap();
+        cpu.break_on_exception(4);
+        assert_eq!(cpu.step(&mut ram), StepOutcome::ExceptionBreak(4));
+        assert_eq!((cpu.pc, cpu.addr_r[7], cpu.sr), (0x100, 0x8000, 0x2700));
+        // The next step takes it.
+        assert_eq!(cpu.step(&mut ram), StepOutcome::Executed);
+        assert_eq!((cpu.pc, cpu.addr_r[7]), (0x300, 0x8000 - 8));
+        let frame: Vec<u32> = (0..4)
+            .map(|i| bus::peek(&mut ram, 0x7ff8 + 2 * i, Size::Word).unwrap())
+            .collect();
+        assert_eq!(frame, [0x2700, 0, 0x100, 4 * 4]);
+    }
+
+    #[test]
+    fn dump_pending_renders_micro_ops() {
+        let (mut cpu, _) = setup(Variant::M68020, &[]);
+        let idx = Some(Index {
+            r: Reg::D(1),
+            long: true,
+            scale: 2,
+        });
+        let ea = EffAddr::AddrIdx {
+            r: Some(0),
+            idx,
+            d: -4,
+        };
+        cpu.load_effaddr(ea, Size::Word);
+        let text = cpu.dump_pending();
+        let scale = text.find("Scale in1,2\n").unwrap();
+        assert!(text[scale..].contains("Add.l in0,in1\n"), "{}", text);
+    }
+
+    #[test]
+    fn write_to_rom_takes_a_bus_error() {
+        // move.w d0,($1000).w
+        let (mut cpu, ram) = setup(Variant::M68000, &[0x31c0, 0x1000]);
+        let mut bus = bus::Protected::new(ram);
+        bus.protect(0x1000, 0x1000, bus::Access::ReadOnly);
+        poke(&mut bus.bus, 2 * 4, Size::Long, 0x300).unwrap();
+        cpu.data_r[0] = 0xffff;
+        cpu.step(&mut bus);
+        assert_eq!(cpu.pc, 0x300);
+        assert_eq!(bus::peek(&mut bus, 0x1000, Size::Word).unwrap(), 0);
+    }
+
+    #[test]
+    fn runs_from_a_borrowed_rom() {
+        // move.w #$1234,($2000).w then move.w ($100).w,d1, out of ROM at
+        // $100. Data reads of the ROM range go to RAM.
+        let rom = [0x31, 0xfc, 0x12, 0x34, 0x20, 0x00, 0x32, 0x38, 0x01, 0x00];
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[]);
+        poke(&mut ram, 0x100, Size::Word, 0xaaaa).unwrap();
+        let mut bus = bus::SplitBus {
+            rom: &rom,
+            base: 0x100,
+            bus: ram,
+        };
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        let stored = bus::peek(&mut bus.bus, 0x2000, Size::Word);
+        assert_eq!(stored.unwrap(), 0x1234);
+        assert_eq!((cpu.data_r[1], cpu.pc), (0xaaaa, 0x10a));
+    }
+
+    #[test]
+    fn diff_reports_old_and_new_values() {
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x7605]);
+        cpu.data_r[3] = 0x1234;
+        let before = cpu.snapshot();
+        let mut after = before;
+        after.data_r[3] = 5;
+        assert_eq!(before.diff(&after), vec![RegChange::D(3, 0x1234, 5)]);
+        cpu.step(&mut ram);
+        assert_eq!(
+            before.diff(&cpu.snapshot()),
+            vec![RegChange::D(3, 0x1234, 5), RegChange::PC(0x100, 0x102)]
+        );
+    }
+
+    #[test]
+    fn divu_overflow_keeps_the_destination() {
+        // divu.w d1,d0
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x80c1]);
+        cpu.data_r[0] = 0x0010_0000;
+        cpu.data_r[1] = 1;
+        cpu.step(&mut ram);
+        assert_eq!(cpu.data_r[0], 0x0010_0000);
+        assert_eq!(cpu.sr & 0x3, 0x2);
+    }
+
+    #[test]
+    fn muls_of_two_negatives_is_positive() {
+        // muls.w d1,d0
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0xc1c1]);
+        cpu.data_r[0] = 0xffff_fffe;
+        cpu.data_r[1] = 0x1234_fffd;
+        cpu.step(&mut ram);
+        assert_eq!(cpu.data_r[0], 6);
+        assert_eq!(cpu.sr & 0xf, 0);
+    }
+
+    #[test]
+    fn divs_remainder_takes_the_dividend_sign() {
+        // divs.w d1,d0, for +-7 / +-2
+        for (dividend, divisor, q, r) in [
+            (7, 2, 3, 1),
+            (-7, 2, -3, -1),
+            (7, -2, -3, 1),
+            (-7, -2, 3, -1),
+        ] {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &[0x81c1]);
+            cpu.data_r[0] = dividend as u32;
+            cpu.data_r[1] = divisor as u32;
+            cpu.step(&mut ram);
+            let res = (r as u32) << 16 | q as u16 as u32;
+            assert_eq!(cpu.data_r[0], res, "{} / {}", dividend, divisor);
+            assert_eq!(cpu.sr & 0xf, if q < 0 { 0x8 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn multiply_takes_38_plus_2n_cycles() {
+        // moveq #0,d2 to prefetch the opcode, then mulu.w d1,d0 or
+        // muls.w d1,d0
+        for (opcode, src, cycles) in [
+            (0xc0c1, 0x0000, 38),
+            (0xc0c1, 0xffff, 38 + 2 * 16),
+            (0xc1c1, 0x5555, 38 + 2 * 16),
+        ] {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &[0x7400, opcode]);
+            cpu.data_r[1] = src;
+            cpu.step(&mut ram);
+            let start = cpu.cycles;
+            cpu.step(&mut ram);
+            let took = cpu.cycles - start;
+            assert_eq!(took, cycles, "{:04x} by {:04x}", opcode, src);
+        }
+    }
+
+    #[test]
+    fn bus_error_with_unmapped_stack_double_faults() {
+        // move.w ($20000).l,d0, past the end of RAM
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x3039, 2, 0]);
+        cpu.addr_r[7] = 0x30000;
+        assert_eq!(cpu.step(&mut ram), StepOutcome::DoubleFault);
+        assert!(matches!(cpu.state, State::Halted));
+    }
+
+    #[test]
+    fn all_sixteen_conditions() {
+        // Condition, then CCRs it holds for and CCRs it fails for, with
+        // N=8, Z=4, V=2 and C=1.
+        let table: [(u8, &[u16], &[u16]); 16] = [
+            (0x0, &[0x0, 0xf], &[]),              // T
+            (0x1, &[], &[0x0, 0xf]),              // F
+            (0x2, &[0x0, 0xa], &[0x1, 0x4, 0x5]), // HI
+            (0x3, &[0x1, 0x4, 0x5], &[0x0, 0xa]), // LS
+            (0x4, &[0x0, 0xe], &[0x1]),           // CC
+            (0x5, &[0x1], &[0x0, 0xe]),           // CS
+            (0x6, &[0x0, 0xb], &[0x4]),           // NE
+            (0x7, &[0x4], &[0x0, 0xb]),           // EQ
+            (0x8, &[0x0, 0xd], &[0x2]),           // VC
+            (0x9, &[0x2], &[0x0, 0xd]),           // VS
+            (0xa, &[0x0, 0x7], &[0x8]),           // PL
+            (0xb, &[0x8], &[0x0, 0x7]),           // MI
+            (0xc, &[0x0, 0xa], &[0x8, 0x2]),      // GE
+            (0xd, &[0x8, 0x2], &[0x0, 0xa]),      // LT
+            (0xe, &[0x0, 0xa], &[0x4, 0x8, 0xe]), // GT
+            (0xf, &[0x4, 0x8, 0xe], &[0x0, 0xa]), // LE
+        ];
+        let mut cpu = M68K::with_state(0, 0x2700, 0);
+        for (cc, holds, fails) in table {
+            for (ccrs, expected) in [(holds, true), (fails, false)] {
+                for &ccr in ccrs {
+                    cpu.sr = 0x2700 | ccr;
+                    let got = cpu.eval_condition(cc);
+                    assert_eq!(got, expected, "cc {:x} ccr {:x}", cc, ccr);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scc_and_dbcc_leave_the_flags() {
+        // st d0, seq d0, dbf d0,*+2 and dbt d0,*+2
+        let programs: [&[u16]; 4] =
+            [&[0x50c0], &[0x57c0], &[0x51c8, 0], &[0x50c8, 0]];
+        for words in programs {
+            for ccr in [0, 0x1f] {
+                let (mut cpu, mut ram) = setup(Variant::M68000, words);
+                cpu.sr = 0x2700 | ccr;
+                cpu.step(&mut ram);
+                assert_eq!(cpu.sr, 0x2700 | ccr, "{:04x}", words[0]);
+                assert_eq!(cpu.pc, 0x100 + 2 * words.len() as u32);
+            }
+        }
+    }
+
+    #[test]
+    fn trapv_traps_on_overflow_only() {
+        // trapv, with V set then every other flag set
+        for (ccr, pc) in [(0x2, 0x300), (0xd, 0x102)] {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &[0x4e76]);
+            poke(&mut ram, 7 * 4, Size::Long, 0x300).unwrap();
+            cpu.sr = 0x2700 | ccr;
+            cpu.step(&mut ram);
+            assert_eq!(cpu.pc, pc);
+        }
+    }
+
+    #[test]
+    fn absolute_short_sign_extends() {
+        // move.w ($8000).w,d0, with memory only at the top of the space
+        let rom = [0x30, 0x38, 0x80, 0x00];
+        let (mut cpu, _) = setup(Variant::M68000, &[]);
+        let mut ram = Ram::new(0xffff_0000, 0x10000);
+        poke(&mut ram, 0xffff_8000, Size::Word, 0x1234).unwrap();
+        let mut bus = bus::SplitBus {
+            rom: &rom,
+            base: 0x100,
+            bus: ram,
+        };
+        cpu.step(&mut bus);
+        assert_eq!((cpu.data_r[0], cpu.pc), (0x1234, 0x104));
+    }
+
+    #[test]
+    fn shift_by_zero_clears_c_and_keeps_x() {
+        // lsl.w d1,d0 with d1 = 0
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0xe368]);
+        cpu.data_r[0] = 0x1234;
+        cpu.sr = 0x2711;
+        cpu.step(&mut ram);
+        assert_eq!((cpu.data_r[0], cpu.sr), (0x1234, 0x2710));
+    }
+
+    #[test]
+    fn byte_pushes_keep_a7_even() {
+        // move.b d0,-(a7) then move.b (a7)+,d1
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x1f00, 0x121f]);
+        cpu.data_r[0] = 0x5a;
+        cpu.step(&mut ram);
+        assert_eq!(cpu.addr_r[7], 0x7ffe);
+        assert_eq!(bus::peek(&mut ram, 0x7ffe, Size::Byte).unwrap(), 0x5a);
+        cpu.step(&mut ram);
+        assert_eq!((cpu.addr_r[7], cpu.data_r[1]), (0x8000, 0x5a));
+    }
+
+    #[test]
+    fn cmp_leaves_x() {
+        // cmp.w d1,d0, borrowing with X clear then not with X set
+        for (x, d0, d1, sr) in [(0, 1, 2, 0x2709), (0x10, 2, 1, 0x2710)] {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &[0xb041]);
+            cpu.sr = 0x2700 | x;
+            cpu.data_r[0] = d0;
+            cpu.data_r[1] = d1;
+            cpu.step(&mut ram);
+            assert_eq!(cpu.sr, sr);
+        }
+    }
+
+    #[test]
+    fn negx_chain_keeps_z_sticky() {
+        // negx.l d0, negx.l d1 then negx.l d2, negating d2:d1:d0 with Z
+        // set beforehand. Zero high longs leave Z clear once it was.
+        let m = 0xffff_ffff;
+        for (value, negated, sr) in [
+            ([1, m, m], [m, 0, 0], 0x2711),
+            ([0, 0, 0], [0, 0, 0], 0x2704),
+        ] {
+            let words = [0x4080, 0x4081, 0x4082];
+            let (mut cpu, mut ram) = setup(Variant::M68000, &words);
+            cpu.data_r[..3].copy_from_slice(&value);
+            cpu.sr = 0x2704;
+            for _ in 0..3 {
+                cpu.step(&mut ram);
+            }
+            assert_eq!(cpu.data_r[..3], negated);
+            assert_eq!(cpu.sr, sr);
+        }
+    }
+
+    #[test]
+    fn nbcd_borrows_and_only_clears_z() {
+        // nbcd d0. N and V are undefined.
+        for (d0, z, res, xzc) in [
+            (0x01, 0x4, 0x99, 0x11),
+            (0x00, 0x4, 0x00, 0x04),
+            (0x00, 0x0, 0x00, 0x00),
+        ] {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &[0x4800]);
+            cpu.data_r[0] = d0;
+            cpu.sr = 0x2700 | z;
+            cpu.step(&mut ram);
+            assert_eq!((cpu.data_r[0], cpu.sr & 0x15), (res, xzc));
+        }
+    }
+
+    #[test]
+    fn move_to_sr_swaps_stacks_and_is_privileged() {
+        // move #0,sr twice, the second one from user mode
+        let words = [0x46fc, 0, 0x46fc, 0];
+        let (mut cpu, mut ram) = setup(Variant::M68000, &words);
+        poke(&mut ram, 8 * 4, Size::Long, 0x300).unwrap();
+        cpu.usp = 0x4000;
+        cpu.step(&mut ram);
+        assert_eq!((cpu.sr, cpu.addr_r[7], cpu.ssp), (0, 0x4000, 0x8000));
+        cpu.step(&mut ram);
+        assert_eq!((cpu.pc, cpu.sr, cpu.usp), (0x300, 0x2000, 0x4000));
+        assert_eq!(cpu.addr_r[7], 0x8000 - 6);
+        // The stacked PC is the one of the faulting instruction.
+        let pc = bus::peek(&mut ram, 0x8000 - 4, Size::Long).unwrap();
+        assert_eq!(pc, 0x104);
+    }
+
+    #[test]
+    fn move_from_sr_is_privileged_from_the_68010() {
+        // move sr,d0 in user mode
+        for (variant, pc, d0) in [
+            (Variant::M68000, 0x102, 0xffff_0004),
+            (Variant::M68010, 0x300, 0xffff_ffff),
+        ] {
+            let (mut cpu, mut ram) = setup(variant, &[0x40c0]);
+            poke(&mut ram, 8 * 4, Size::Long, 0x300).unwrap();
+            cpu.sr = 0x0004;
+            cpu.ssp = 0x8000;
+            cpu.addr_r[7] = 0x4000;
+            cpu.data_r[0] = 0xffff_ffff;
+            cpu.step(&mut ram);
+            assert_eq!((cpu.pc, cpu.data_r[0]), (pc, d0));
+        }
+    }
+
+    #[test]
+    fn leaving_the_master_stack_for_user_mode() {
+        // move #0,sr from the master stack, then trap #0 back into the
+        // supervisor, which now uses the interrupt stack.
+        let words = [0x46fc, 0, 0x4e40];
+        let (mut cpu, mut ram) = setup(Variant::M68020, &words);
+        poke(&mut ram, 32 * 4, Size::Long, 0x300).unwrap();
+        cpu.sr = 0x3700;
+        cpu.addr_r[7] = 0x9000;
+        cpu.ssp = 0x8000;
+        cpu.usp = 0x4000;
+        cpu.step(&mut ram);
+        assert_eq!((cpu.sr, cpu.addr_r[7], cpu.msp), (0, 0x4000, 0x9000));
+        cpu.step(&mut ram);
+        assert_eq!((cpu.pc, cpu.sr, cpu.addr_r[7]), (0x300, 0x2000, 0x7ff8));
+        assert_eq!((cpu.usp, cpu.msp), (0x4000, 0x9000));
+    }
+
+    #[test]
+    fn lowering_the_mask_takes_a_pending_interrupt() {
+        // andi #$f8ff,sr and eori #$0700,sr, with level 4 requested
+        for (opcode, imm) in [(0x027c, 0xf8ff), (0x0a7c, 0x0700)] {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &[opcode, imm]);
+            poke(&mut ram, 28 * 4, Size::Long, 0x300).unwrap();
+            cpu.set_irq(4, true);
+            cpu.step(&mut ram);
+            assert_eq!((cpu.pc, cpu.sr), (0x300, 0x2400), "{:04x}", opcode);
+            let pc = bus::peek(&mut ram, 0x8000 - 4, Size::Long).unwrap();
+            assert_eq!(pc, 0x104);
+        }
+    }
+
+    #[test]
+    fn pending_exceptions_in_priority_order() {
+        // moveq #1,d0 then move.w (a0),d0 from an odd address, each traced
+        // with a level 4 interrupt requested. The interrupt frame ends up on
+        // top, over the trace frame of moveq, and over the address error
+        // frame for the move, which was never traced as it did not complete.
+        for (opcode, sp, under) in
+            [(0x7001, 0x7ff4, 0x400), (0x3010, 0x7fec, 0x500)]
+        {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &[opcode]);
+            for (vector, handler) in [(3, 0x500), (9, 0x400), (28, 0x300)] {
+                poke(&mut ram, vector * 4, Size::Long, handler).unwrap();
+            }
+            cpu.sr = 0xa000;
+            cpu.addr_r[0] = 0x1001;
+            cpu.set_irq(4, true);
+            cpu.step(&mut ram);
+            assert_eq!((cpu.pc, cpu.addr_r[7]), (0x300, sp));
+            let pc = bus::peek(&mut ram, sp + 2, Size::Long).unwrap();
+            assert_eq!(pc, under);
+        }
+    }
+
+    #[test]
+    fn trace_after_one_instruction() {
+        // moveq #1,d0 with T set
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x7001, 0x7002]);
+        poke(&mut ram, 9 * 4, Size::Long, 0x400).unwrap();
+        cpu.sr = 0xa700;
+        cpu.step(&mut ram);
+        assert_eq!((cpu.data_r[0], cpu.pc, cpu.sr), (1, 0x400, 0x2700));
+        let sr = bus::peek(&mut ram, 0x7ffa, Size::Word).unwrap();
+        let pc = bus::peek(&mut ram, 0x7ffc, Size::Long).unwrap();
+        assert_eq!((sr, pc), (0xa700, 0x102));
+    }
+
+    #[test]
+    fn stacked_pc_per_exception() {
+        // trap #0 and chk.w d1,d0 stack the next instruction, an illegal
+        // opcode its own address.
+        for (words, vector, stacked) in [
+            (&[0x4e40][..], 32, 0x102),
+            (&[0x4181][..], 6, 0x102),
+            (&[0x4afc][..], 4, 0x100),
+        ] {
+            let (mut cpu, mut ram) = setup(Variant::M68000, words);
+            poke(&mut ram, vector * 4, Size::Long, 0x300).unwrap();
+            cpu.data_r[0] = 5;
+            cpu.data_r[1] = 3;
+            cpu.step(&mut ram);
+            assert_eq!(cpu.pc, 0x300);
+            let pc = bus::peek(&mut ram, 0x8000 - 4, Size::Long).unwrap();
+            assert_eq!(pc, stacked, "vector {}", vector);
+        }
+    }
+
+    #[test]
+    fn chk_sets_n_by_bound() {
+        // chk.w d1,d0 against a bound of 3
+        for (d0, pc, n) in [(0xffff, 0x300, 0x8), (4, 0x300, 0), (3, 0x102, 0)]
+        {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &[0x4181]);
+            poke(&mut ram, 6 * 4, Size::Long, 0x300).unwrap();
+            cpu.data_r[0] = d0;
+            cpu.data_r[1] = 3;
+            cpu.sr = 0x2708;
+            cpu.step(&mut ram);
+            assert_eq!((cpu.pc, cpu.sr & 0x8), (pc, n), "{:x}", d0);
+        }
+    }
+
+    #[test]
+    fn fault_frame_records_the_access() {
+        // move.w (a0),d0 on the 68000, with a0 odd: status word of a
+        // supervisor data read, address, then the opcode.
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x3010]);
+        cpu.addr_r[0] = 0x0001_2345;
+        cpu.step(&mut ram);
+        let frame: Vec<u32> = (0..5)
+            .map(|i| bus::peek(&mut ram, 0x7ff2 + 2 * i, Size::Word).unwrap())
+            .collect();
+        assert_eq!(frame, [0x1d, 0x0001, 0x2345, 0x3010, 0x2700]);
+        // move.w d0,(a0) on the 68010: format and vector offset, then the
+        // status word of a supervisor data write and the address.
+        let (mut cpu, mut ram) = setup(Variant::M68010, &[0x3080]);
+        cpu.addr_r[0] = 0x0001_2345;
+        cpu.step(&mut ram);
+        let sp = cpu.addr_r[7];
+        let frame: Vec<u32> = (3..7)
+            .map(|i| bus::peek(&mut ram, sp + 2 * i, Size::Word).unwrap())
+            .collect();
+        assert_eq!(frame, [0x800c, 0x0005, 0x0001, 0x2345]);
+    }
+
+    #[test]
+    fn read_modify_writes_lock_the_bus() {
+        use Event::*;
+        let fc = FunctionCode::SupervisorData;
+        // tas (a0) on the 68000, cas.w d0,d1,(a0) on the 68020
+        for (variant, words, s) in [
+            (Variant::M68000, &[0x4ad0][..], Size::Byte),
+            (Variant::M68020, &[0x0cd0, 0x0040][..], Size::Word),
+        ] {
+            let (mut cpu, ram) = setup(variant, words);
+            let mut bus = Recorder::new(ram);
+            cpu.addr_r[0] = 0x2000;
+            cpu.step(&mut bus);
+            let log: Vec<&Event> =
+                bus.log.iter().skip_while(|e| **e != Lock(true)).collect();
+            let rmw = [
+                Lock(true),
+                Read(0x2000, s, fc),
+                Write(0x2000, s, fc),
+                Lock(false),
+            ];
+            assert_eq!(log, rmw.iter().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn cas2_updates_both_or_loads_both() {
+        // cas2.w d0:d1,d2:d3,(a0):(a1)
+        for (d1, z) in [(0x2222, true), (0x9999, false)] {
+            let words = [0x0cfc, 0x8080, 0x90c1];
+            let (mut cpu, mut ram) = setup(Variant::M68020, &words);
+            poke(&mut ram, 0x2000, Size::Word, 0x1111).unwrap();
+            poke(&mut ram, 0x2010, Size::Word, 0x2222).unwrap();
+            cpu.addr_r[0] = 0x2000;
+            cpu.addr_r[1] = 0x2010;
+            cpu.data_r[..4].copy_from_slice(&[0x1111, d1, 0xaaaa, 0xbbbb]);
+            cpu.step(&mut ram);
+            let mem = [0x2000, 0x2010]
+                .map(|a| bus::peek(&mut ram, a, Size::Word).unwrap());
+            if z {
+                assert_eq!(mem, [0xaaaa, 0xbbbb]);
+            } else {
+                assert_eq!(mem, [0x1111, 0x2222]);
+                assert_eq!(cpu.data_r[..2], [0x1111, 0x2222]);
+            }
+            assert_eq!(cpu.sr & 0x4 != 0, z);
+        }
+    }
+
+    #[test]
+    fn reset_instruction_only_resets_devices() {
+        // moveq #0,d2 to prefetch the opcode, then reset
+        let (mut cpu, ram) = setup(Variant::M68000, &[0x7400, 0x4e70]);
+        let mut bus = Recorder::new(ram);
+        cpu.step(&mut bus);
+        let (before, start) = (cpu.snapshot(), cpu.cycles);
+        cpu.step(&mut bus);
+        // The prefetch, then the line held.
+        assert_eq!(cpu.cycles - start, 4 + 124);
+        assert_eq!(before.diff(&cpu.snapshot()), [RegChange::PC(0x102, 0x104)]);
+        let resets = bus.log.iter().filter(|e| **e == Event::Reset).count();
+        assert_eq!(resets, 1);
+    }
+
+    #[test]
+    fn clr_reads_before_writing_on_the_68000() {
+        use Event::*;
+        // clr.w (a0)
+        let fc = FunctionCode::SupervisorData;
+        let read = Read(0x2000, Size::Word, fc);
+        let write = Write(0x2000, Size::Word, fc);
+        for (variant, reads) in [(Variant::M68000, 1), (Variant::M68010, 0)] {
+            let (mut cpu, ram) = setup(variant, &[0x4250]);
+            let mut bus = Recorder::new(ram);
+            cpu.addr_r[0] = 0x2000;
+            cpu.step(&mut bus);
+            bus.log.retain(|e| *e == read || *e == write);
+            let mut expected = vec![read.clone(); reads];
+            expected.push(write.clone());
+            assert_eq!(bus.log, expected);
+        }
+    }
+
+    #[test]
+    fn pc_relative_lea_reads_no_data() {
+        // lea *+$12(pc),a0
+        let (mut cpu, ram) = setup(Variant::M68000, &[0x41fa, 0x10]);
+        let mut bus = Recorder::new(ram);
+        bus.data_reads_fail = true;
+        cpu.step(&mut bus);
+        assert_eq!((cpu.addr_r[0], cpu.pc), (0x112, 0x104));
+    }
+
+    #[test]
+    fn moveq_only_writes_its_data_register() {
+        // moveq #-1,d0
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x70ff]);
+        cpu.addr_r = [0x1111_1111; 8];
+        let before = cpu.snapshot();
+        cpu.step(&mut ram);
+        let changes = [
+            RegChange::D(0, 0, 0xffff_ffff),
+            RegChange::PC(0x100, 0x102),
+            RegChange::Flag(Flag::Negative, false, true),
+        ];
+        assert_eq!(before.diff(&cpu.snapshot()), changes);
+        // The same with bit 8 set is illegal.
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x71ff]);
+        poke(&mut ram, 4 * 4, Size::Long, 0x300).unwrap();
+        cpu.step(&mut ram);
+        assert_eq!((cpu.pc, cpu.data_r[0]), (0x300, 0));
+    }
+
+    #[test]
+    fn addq_to_an_is_long_and_keeps_ccr() {
+        // addq.w #1,a0
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x5248]);
+        cpu.addr_r[0] = 0xffff;
+        cpu.sr = 0x271f;
+        cpu.step(&mut ram);
+        assert_eq!((cpu.addr_r[0], cpu.sr), (0x1_0000, 0x271f));
+    }
+
+    #[test]
+    fn tst_an_from_the_68020() {
+        // tst.w a0
+        for (variant, pc, sr) in [
+            (Variant::M68000, 0x300, 0x2700),
+            (Variant::M68020, 0x102, 0x2708),
+        ] {
+            let (mut cpu, mut ram) = setup(variant, &[0x4a48]);
+            poke(&mut ram, 4 * 4, Size::Long, 0x300).unwrap();
+            cpu.addr_r[0] = 0x8000;
+            cpu.step(&mut ram);
+            assert_eq!((cpu.pc, cpu.sr), (pc, sr));
+        }
+    }
+
+    #[test]
+    fn bra_w_with_zero_displacement() {
+        // bra.w *+2, the displacement being relative to its own word
+        let (mut cpu, ram) = setup(Variant::M68000, &[0x6000, 0]);
+        let mut bus = Recorder::new(ram);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.pc, 0x102);
+        let fc = FunctionCode::SupervisorProgram;
+        assert!(bus.log.contains(&Event::Read(0x102, Size::Word, fc)));
+    }
+
+    #[test]
+    fn move_abs_long_to_abs_long() {
+        // move.l ($2000).l,($3000).l
+        let words = [0x23f9, 0, 0x2000, 0, 0x3000];
+        let (mut cpu, mut ram) = setup(Variant::M68000, &words);
+        poke(&mut ram, 0x2000, Size::Long, 0xdead_beef).unwrap();
+        let fetches = Rc::new(RefCell::new(Vec::new()));
+        let log = fetches.clone();
+        cpu.fetch_trace =
+            Some(Box::new(move |a, x| log.borrow_mut().push((a, x))));
+        cpu.step(&mut ram);
+        let expected: Vec<_> =
+            (0..5).map(|i| (0x100 + 2 * i as u32, words[i])).collect();
+        assert_eq!(*fetches.borrow(), expected);
+        assert_eq!(cpu.pc, 0x10a);
+        let moved = bus::peek(&mut ram, 0x3000, Size::Long).unwrap();
+        assert_eq!(moved, 0xdead_beef);
+    }
+
+    #[test]
+    fn move_an_to_predecrement_of_itself() {
+        // move.l a7,-(a7) pushes A7 as it was before the decrement.
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x2f0f]);
+        cpu.step(&mut ram);
+        assert_eq!(cpu.addr_r[7], 0x7ffc);
+        let pushed = bus::peek(&mut ram, 0x7ffc, Size::Long).unwrap();
+        assert_eq!(pushed, 0x8000);
+    }
+
+    #[test]
+    fn move_between_predecrements_of_one_register() {
+        // move.l -(a0),-(a0): the source decrement happens first, then the
+        // destination one, copying the long below A0 one long further down.
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x2120]);
+        poke(&mut ram, 0x2004, Size::Long, 0x1234_5678).unwrap();
+        cpu.addr_r[0] = 0x2008;
+        cpu.step(&mut ram);
+        assert_eq!(cpu.addr_r[0], 0x2000);
+        let copied = bus::peek(&mut ram, 0x2000, Size::Long).unwrap();
+        assert_eq!(copied, 0x1234_5678);
+    }
+
+    #[test]
+    fn mode_7_sub_modes() {
+        let d1 = Some(Index {
+            r: Reg::D(1),
+            long: false,
+            scale: 0,
+        });
+        let cases = [
+            (0o70, &[0x1234][..], EffAddr::AbsShort { addr: 0x1234 }),
+            (
+                0o71,
+                &[1, 0x2345][..],
+                EffAddr::AbsLong { hi: 1, lo: 0x2345 },
+            ),
+            (
+                0o72,
+                &[0xfffe][..],
+                EffAddr::PCIndDisp { base: 0x102, d: -2 },
+            ),
+            (
+                0o73,
+                &[0x1004][..],
+                EffAddr::PCIndIdx {
+                    base: Some(0x102),
+                    idx: d1,
+                    d: 4,
+                },
+            ),
+            (0o74, &[0xbeef][..], EffAddr::Immediate { x: 0xbeef }),
+        ];
+        // The extension words follow an opcode at $100.
+        for (field, ext, ea) in cases {
+            let words = [&[0][..], ext].concat();
+            let (mut cpu, mut ram) = setup(Variant::M68000, &words);
+            cpu.pc = 0x102;
+            let decoded = cpu.decode_effaddr(&mut ram, field, Size::Word);
+            assert_eq!(decoded, Ok(ea));
+            assert_eq!(cpu.pc, 0x102 + 2 * ext.len() as u32, "{:?}", ea);
+        }
+        for field in 0o75..=0o77 {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &[0, 0]);
+            cpu.pc = 0x102;
+            let decoded = cpu.decode_effaddr(&mut ram, field, Size::Word);
+            assert_eq!(decoded, Err(4), "{:o}", field);
+        }
+    }
+
+    #[test]
+    fn negative_scaled_word_index() {
+        // lea 4(a0,d0.w*4),a1 with d0.w = -2
+        let (mut cpu, mut ram) = setup(Variant::M68020, &[0x43f0, 0x0404]);
+        cpu.addr_r[0] = 0x2000;
+        cpu.data_r[0] = 0x1234_fffe;
+        cpu.step(&mut ram);
+        assert_eq!(cpu.addr_r[1], 0x2000 - 8 + 4);
+    }
+
+    #[test]
+    fn pc_displacement_wraps_below_zero() {
+        // lea -$200(pc),a0, from $102
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x41fa, 0xfe00]);
+        cpu.step(&mut ram);
+        assert_eq!(cpu.addr_r[0], 0xffff_ff02);
+    }
+
+    #[test]
+    fn pc_relative_base_is_the_extension_word() {
+        // move.w 6(pc),($3000).l: the source is relative to $102, however
+        // many words follow it.
+        let words = [0x33fa, 6, 0, 0x3000, 0xabcd];
+        let (mut cpu, mut ram) = setup(Variant::M68000, &words);
+        cpu.step(&mut ram);
+        let moved = bus::peek(&mut ram, 0x3000, Size::Word).unwrap();
+        assert_eq!((moved, cpu.pc), (0xabcd, 0x108));
+        // The same on the 68020 with a full format extension word and a
+        // long base displacement: move.w (8.l,pc),($3000).l reads its own
+        // last word.
+        let words = [0x33fb, 0x0170, 0, 8, 0, 0x3000];
+        let (mut cpu, mut ram) = setup(Variant::M68020, &words);
+        cpu.step(&mut ram);
+        let moved = bus::peek(&mut ram, 0x3000, Size::Word).unwrap();
+        assert_eq!((moved, cpu.pc), (0x3000, 0x10c));
+    }
+
+    #[test]
+    fn vectors_are_fetched_through_vbr() {
+        // illegal, with the table moved to $4000
+        let (mut cpu, mut ram) = setup(Variant::M68010, &[0x4afc]);
+        poke(&mut ram, 4 * 4, Size::Long, 0x500).unwrap();
+        poke(&mut ram, 0x4000 + 4 * 4, Size::Long, 0x300).unwrap();
+        cpu.vbr = 0x4000;
+        cpu.step(&mut ram);
+        assert_eq!(cpu.pc, 0x300);
+    }
+
+    #[test]
+    fn movec_of_an_unknown_register_is_illegal() {
+        // movec d0,$fff then movec d0,vbr
+        let words = [0x4e7b, 0x0fff, 0x4e7b, 0x0801];
+        let (mut cpu, mut ram) = setup(Variant::M68010, &words);
+        poke(&mut ram, 4 * 4, Size::Long, 0x104).unwrap();
+        cpu.data_r[0] = 0x4000;
+        cpu.step(&mut ram);
+        assert_eq!((cpu.pc, cpu.addr_r[7]), (0x104, 0x8000 - 8));
+        cpu.step(&mut ram);
+        assert_eq!(cpu.vbr, 0x4000);
+    }
+
+    #[test]
+    fn fetches_and_operands_use_their_own_spaces() {
+        use FunctionCode::*;
+        // move.w (a0),d0, from supervisor then user mode
+        for (sr, program, data) in [
+            (0x2700, SupervisorProgram, SupervisorData),
+            (0x0700, UserProgram, UserData),
+        ] {
+            let (mut cpu, ram) = setup(Variant::M68000, &[0x3010]);
+            let mut bus = Recorder::new(ram);
+            cpu.sr = sr;
+            cpu.addr_r[0] = 0x2000;
+            cpu.step(&mut bus);
+            let w = Size::Word;
+            let reads = [
+                Event::Read(0x100, w, program),
+                Event::Read(0x102, w, program),
+                Event::Read(0x2000, w, data),
+            ];
+            assert_eq!(bus.log, reads);
+        }
+    }
+
+    #[test]
+    fn waking_from_stop_takes_an_idle_cycle() {
+        // Cycles of a step after stop #$2000, or of moveq #0,d2, with or
+        // without a level 4 interrupt requested.
+        let run = |stop: bool, irq: bool| {
+            let words: &[u16] = match stop {
+                true => &[0x4e72, 0x2000],
+                false => &[0x7400],
+            };
+            let (mut cpu, mut ram) = setup(Variant::M68000, words);
+            poke(&mut ram, 28 * 4, Size::Long, 0x300).unwrap();
+            cpu.sr = 0x2000;
+            if stop {
+                cpu.step(&mut ram);
+                assert!(matches!(cpu.state, State::Stopped));
+            }
+            let start = cpu.cycles;
+            cpu.set_irq(4, irq);
+            cpu.step(&mut ram);
+            assert_eq!(cpu.pc == 0x300, irq);
+            cpu.cycles - start
+        };
+        let interrupt = run(false, true) - run(false, false);
+        let idle = run(true, false);
+        assert_eq!(run(true, true), idle + interrupt + STOP_WAKEUP_CYCLES);
+    }
+
+    #[test]
+    fn periodic_timer_fires_on_its_deadline() {
+        // Idling in the stopped state, 4 cycles a step, until a timer of
+        // 40 cycles interrupts at level 5 through vector $50.
+        let run = || {
+            let (mut cpu, mut ram) = setup(Variant::M68000, &[]);
+            poke(&mut ram, 0x50 * 4, Size::Long, 0x300).unwrap();
+            cpu.state = State::Stopped;
+            cpu.sr = 0x2000;
+            cpu.add_periodic(40, 5, 0x50);
+            let mut starts = Vec::new();
+            while cpu.pc != 0x300 {
+                starts.push(cpu.cycles);
+                cpu.step(&mut ram);
+            }
+            (starts, cpu.cycles, cpu.sr)
+        };
+        let (starts, cycles, sr) = run();
+        assert_eq!(starts, (0..10).map(|i| 4 * i).collect::<Vec<u64>>());
+        assert_eq!(sr, 0x2500);
+        assert_eq!(run(), (starts, cycles, sr));
+    }
+
+    #[test]
+    fn vector_writes_end_the_step() {
+        // move.l d0,$10.w overwrites vector 4, move.l d0,$400.w is past the
+        // table.
+        let words = [0x21c0, 0x0010, 0x21c0, 0x0400];
+        let (mut cpu, mut ram) = setup(Variant::M68000, &words);
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let log = written.clone();
+        cpu.watch_vectors(Box::new(move |v| log.borrow_mut().push(v)));
+        assert_eq!(cpu.step(&mut ram), StepOutcome::VectorWrite(4));
+        assert_eq!(cpu.step(&mut ram), StepOutcome::Executed);
+        assert_eq!(*written.borrow(), [4]);
+    }
+
+    #[test]
+    fn step_microop_runs_one_at_a_time() {
+        // move.w (a0),d0
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x3010]);
+        poke(&mut ram, 0x2000, Size::Word, 0x1234).unwrap();
+        cpu.addr_r[0] = 0x2000;
+        let outcome = cpu.step_microop(&mut ram);
+        assert_eq!(outcome, MicroStepOutcome::MidInstruction);
+        let mut left = cpu.pending_microops().len();
+        while left > 1 {
+            let outcome = cpu.step_microop(&mut ram);
+            assert_eq!(outcome, MicroStepOutcome::MidInstruction);
+            assert_eq!(cpu.pending_microops().len(), left - 1);
+            assert_eq!(cpu.data_r[0], 0);
+            left -= 1;
+        }
+        let outcome = cpu.step_microop(&mut ram);
+        assert_eq!(outcome, MicroStepOutcome::Completed);
+        assert_eq!((cpu.data_r[0], cpu.pc), (0x1234, 0x102));
+    }
+
+    #[test]
+    fn injected_microops_run_before_the_next_instruction() {
+        // move.l d0,d2 sees d0 = 5 + 3
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x2400]);
+        cpu.data_r[1] = 3;
+        cpu.inject_microops([
+            MicroI::Set(Reg::D(0), 5),
+            MicroI::Alu {
+                op: AluOp::Add,
+                dst: Reg::D(0),
+                src: Reg::D(1),
+                size: Size::Long,
+                set_flags: false,
+            },
+        ]);
+        cpu.step(&mut ram);
+        assert_eq!((cpu.data_r[0], cpu.data_r[2], cpu.pc), (8, 8, 0x102));
+        assert!(cpu.pending_microops().is_empty());
+    }
+
+    #[test]
+    fn peek_and_poke_leave_the_cpu_alone() {
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x7001]);
+        cpu.step(&mut ram);
+        let seen = Rc::new(RefCell::new(0));
+        let count = seen.clone();
+        cpu.mem_trace = Some(Box::new(move |_| *count.borrow_mut() += 1));
+        let (before, cycles) = (cpu.snapshot(), cpu.cycles);
+        // A long at an odd address is fine for the debugger.
+        poke(&mut ram, 0x2001, Size::Long, 0x1234_5678).unwrap();
+        let x = bus::peek(&mut ram, 0x2001, Size::Long).unwrap();
+        assert_eq!(x, 0x1234_5678);
+        assert!(before.diff(&cpu.snapshot()).is_empty());
+        assert_eq!((cpu.cycles, *seen.borrow()), (cycles, 0));
+    }
+
+    #[test]
+    fn refresh_penalty_slows_bus_cycles() {
+        // moveq #1,d0, fetching its opcode and prefetching the next word
+        let run = |wait_states| {
+            let (mut cpu, ram) = setup(Variant::M68000, &[0x7001]);
+            let mut bus = Recorder::new(ram);
+            bus.wait_states = wait_states;
+            cpu.step(&mut bus);
+            cpu.cycles
+        };
+        assert_eq!(run(2), run(0) + 2 * 2);
+    }
+
+    #[test]
+    fn stats_per_class() {
+        // moveq #1,d0, moveq #2,d1, add.w d0,d1 then lsl.w #1,d1
+        let words = [0x7001, 0x7202, 0xd240, 0xe349];
+        let (mut cpu, mut ram) = setup(Variant::M68000, &words);
+        cpu.collect_stats();
+        let mut took = Vec::new();
+        for _ in 0..4 {
+            let start = cpu.cycles;
+            cpu.step(&mut ram);
+            took.push(cpu.cycles - start);
+        }
+        assert_eq!(cpu.data_r[1], 6);
+        let stats = cpu.take_stats().unwrap().per_class;
+        let class = |count, cycles| ClassStats { count, cycles };
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[&InstrClass::Move], class(2, took[0] + took[1]));
+        assert_eq!(stats[&InstrClass::Arithmetic], class(1, took[2]));
+        assert_eq!(stats[&InstrClass::Shift], class(1, took[3]));
+    }
+
+    #[test]
+    fn set_and_clear_each_flag() {
+        use Flag::*;
+        let mut cpu = M68K::with_state(0, 0x2700, 0);
+        for (f, mask) in [
+            (Carry, 0x01),
+            (Overflow, 0x02),
+            (Zero, 0x04),
+            (Negative, 0x08),
+            (Extend, 0x10),
+        ] {
+            cpu.set_flag(f, true);
+            assert!(cpu.get_flag(f));
+            assert_eq!(cpu.sr, 0x2700 | mask);
+            cpu.set_flag(f, false);
+            assert!(!cpu.get_flag(f));
+            assert_eq!(cpu.sr, 0x2700);
+        }
+    }
+
+    #[test]
+    fn alu_flags_per_op() {
+        use AluOp::*;
+        use Size::*;
+        // Operation, destination, source, size, CCR before, then the result
+        // and CCR after, with X=$10, N=8, Z=4, V=2 and C=1.
+        let cases = [
+            (Add, 0x7f, 0x01, Byte, 0x00, 0x80, 0x0a),
+            (Add, 0xffff, 0x0001, Word, 0x00, 0x0000, 0x15),
+            (Sub, 0, 1, Long, 0x00, 0xffff_ffff, 0x19),
+            (Sub, 0x80, 0x01, Byte, 0x10, 0x7f, 0x02),
+            (Cmp, 0x0001, 0x0002, Word, 0x10, 0xffff, 0x19),
+            (And, 0xf0f0, 0xff00, Long, 0x13, 0xf000, 0x10),
+            (Or, 0x80, 0x01, Byte, 0x00, 0x81, 0x08),
+            (Eor, 0xffff, 0xffff, Word, 0x00, 0x0000, 0x04),
+            (Addx, 0xff, 0x00, Byte, 0x14, 0x00, 0x15),
+            (Subx, 0x0000, 0x0000, Word, 0x14, 0xffff, 0x19),
+            (Move, 0, 0x8000, Word, 0x13, 0x8000, 0x18),
+        ];
+        let mut cpu = M68K::with_state(0, 0x2700, 0);
+        for (op, a, b, s, before, x, after) in cases {
+            cpu.sr = 0x2700 | before;
+            assert_eq!(cpu.alu(op, a, b, s, true), x, "{:?}", op);
+            assert_eq!(cpu.sr, 0x2700 | after, "{:?}", op);
+        }
+    }
+
+    #[test]
+    fn boot_loads_the_reset_vectors() {
+        let mut ram = Ram::new(0, 0x10000);
+        poke(&mut ram, 0, Size::Long, 0x8000).unwrap();
+        poke(&mut ram, 4, Size::Long, 0x400).unwrap();
+        let cpu = boot(&mut ram);
+        let s = cpu.snapshot();
+        assert_eq!((s.addr_r[7], s.pc, s.sr), (0x8000, 0x400, 0x2700));
+    }
+
+    #[test]
+    fn execute_opcode_from_a_slice() {
+        // addq.l #3,d0 then addq.w #1,($2000).w, neither of which is in
+        // memory at PC.
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[]);
+        poke(&mut ram, 0x2000, Size::Word, 0x41).unwrap();
+        cpu.data_r[0] = 1;
+        assert_eq!(cpu.execute_opcode(&[0x5680], &mut ram), Ok(()));
+        assert_eq!((cpu.data_r[0], cpu.pc), (4, 0x102));
+        assert_eq!(cpu.execute_opcode(&[0x5278, 0x2000], &mut ram), Ok(()));
+        let x = bus::peek(&mut ram, 0x2000, Size::Word).unwrap();
+        assert_eq!((x, cpu.pc), (0x42, 0x106));
+    }
+
+    #[test]
+    fn trap_handler_replaces_the_exception() {
+        // trap #3, handled by the host
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0x4e43]);
+        poke(&mut ram, 35 * 4, Size::Long, 0x300).unwrap();
+        cpu.set_trap_handler(
+            3,
+            Box::new(|cpu: &mut M68K, _: &mut dyn Bus| cpu.data_r[0] = 0x42),
+        );
+        cpu.step(&mut ram);
+        assert_eq!(cpu.data_r[0], 0x42);
+        assert_eq!((cpu.pc, cpu.addr_r[7]), (0x102, 0x8000));
+    }
+
+    #[test]
+    fn move_between_memory_operands_timing() {
+        // moveq #0,d2 to prefetch the opcode, then each move, against the
+        // 68000 MOVE timing tables
+        for (words, cycles) in [
+            (&[0x3290][..], 12),                       // move.w (a0),(a1)
+            (&[0x2290][..], 20),                       // move.l (a0),(a1)
+            (&[0x32a0][..], 14),                       // move.w -(a0),(a1)
+            (&[0x32b0, 0x1004][..], 18),               // move.w 4(a0,d1.w),(a1)
+            (&[0x3380, 0x1004][..], 14),               // move.w d0,4(a1,d1.w)
+            (&[0x23f9, 0, 0x2000, 0, 0x3000][..], 36), // move.l abs.l,abs.l
+        ] {
+            let (mut cpu, mut ram) =
+                setup(Variant::M68000, &[&[0x7400][..], words].concat());
+            cpu.addr_r[0] = 0x2000;
+            cpu.addr_r[1] = 0x3000;
+            cpu.step(&mut ram);
+            let start = cpu.cycles;
+            cpu.step(&mut ram);
+            assert_eq!(cpu.cycles - start, cycles, "{:04x}", words[0]);
+        }
+    }
+
+    #[test]
+    fn addx_register_and_memory_forms() {
+        // addx.l d1,d2
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0xd581]);
+        cpu.set_flag(Flag::Extend, true);
+        (cpu.data_r[1], cpu.data_r[2]) = (0x10, 0x20);
+        cpu.step(&mut ram);
+        assert_eq!((cpu.data_r[1], cpu.data_r[2]), (0x10, 0x31));
+        // addx.w -(a1),-(a2)
+        let (mut cpu, mut ram) = setup(Variant::M68000, &[0xd549]);
+        cpu.set_flag(Flag::Extend, true);
+        (cpu.addr_r[1], cpu.addr_r[2]) = (0x2002, 0x3002);
+        poke(&mut ram, 0x2000, Size::Word, 0x10).unwrap();
+        poke(&mut ram, 0x3000, Size::Word, 0x20).unwrap();
+        cpu.step(&mut ram);
+        assert_eq!((cpu.addr_r[1], cpu.addr_r[2]), (0x2000, 0x3000));
+        let sum = bus::peek(&mut ram, 0x3000, Size::Word).unwrap();
+        assert_eq!(sum, 0x31);
+        assert_eq!(bus::peek(&mut ram, 0x2000, Size::Word).unwrap(), 0x10);
+    }
+
+    #[test]
+    fn reads_ignore_undriven_lines() {
+        // move.b (a0),d0 ; add.b (a0),d1 ; move.l (a1),d2
+        let words = [0x1010, 0xd210, 0x2411];
+        let (mut cpu, mut ram) = setup(Variant::M68000, &words);
+        poke(&mut ram, 0x2000, Size::Byte, 0xff).unwrap();
+        poke(&mut ram, 0x2004, Size::Long, 0x12345678).unwrap();
+        let mut bus = Recorder::new(ram);
+        bus.lines_high = true;
+        (cpu.addr_r[0], cpu.addr_r[1]) = (0x2000, 0x2004);
+        (cpu.data_r[0], cpu.data_r[1]) = (0x12345678, 0x12345601);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.data_r[0], 0x123456ff);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.data_r[1], 0x12345600);
+        assert!(cpu.get_flag(Flag::Zero) && cpu.get_flag(Flag::Carry));
+        // Two word cycles on the 68000 bus, each with its upper lines high.
+        cpu.step(&mut bus);
+        assert_eq!(cpu.data_r[2], 0x12345678);
+    }
+}
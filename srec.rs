@@ -0,0 +1,112 @@
+use crate::bus::{Bus, FunctionCode};
+use crate::Size;
+
+// What went wrong loading an S-record file. Lines are numbered from 1.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SRecError {
+    Malformed(usize),
+    Checksum(usize),
+    // Writing the data at this address was a bus error.
+    Bus(u32),
+    // No S7, S8 or S9 record gave the entry point.
+    NoEntry,
+}
+
+// Writes the data of the S1, S2 and S3 records of `text` to `bus`, returning
+// the entry point of the S7, S8 or S9 record.
+pub fn load_srecord(bus: &mut impl Bus, text: &str) -> Result<u32, SRecError> {
+    let mut entry = None;
+    for (i, line) in text.lines().enumerate() {
+        let n = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (kind, bytes) = parse_line(line).ok_or(SRecError::Malformed(n))?;
+        let sum = bytes.iter().fold(0u8, |s, &b| s.wrapping_add(b));
+        if sum != 0xff {
+            return Err(SRecError::Checksum(n));
+        }
+        // Byte count, address, data and checksum.
+        let addr_len = match kind {
+            b'0' | b'1' | b'5' | b'9' => 2,
+            b'2' | b'6' | b'8' => 3,
+            b'3' | b'7' => 4,
+            _ => return Err(SRecError::Malformed(n)),
+        };
+        if bytes.len() < 2 + addr_len || bytes[0] as usize != bytes.len() - 1 {
+            return Err(SRecError::Malformed(n));
+        }
+        let addr = bytes[1..1 + addr_len]
+            .iter()
+            .fold(0u32, |a, &b| a << 8 | b as u32);
+        let data = &bytes[1 + addr_len..bytes.len() - 1];
+        match kind {
+            b'1'..=b'3' => {
+                for (j, &b) in data.iter().enumerate() {
+                    let a = addr.wrapping_add(j as u32);
+                    let fc = FunctionCode::SupervisorData;
+                    bus.write(a, Size::Byte, b as u32, fc)
+                        .map_err(|_| SRecError::Bus(a))?;
+                }
+            }
+            b'7'..=b'9' => entry = Some(addr),
+            // Header and record counts.
+            _ => (),
+        }
+    }
+    entry.ok_or(SRecError::NoEntry)
+}
+
+// The record type and the bytes following it.
+fn parse_line(line: &str) -> Option<(u8, Vec<u8>)> {
+    let (head, hex) = line.split_at_checked(2)?;
+    let kind = head.strip_prefix('S')?.as_bytes()[0];
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    if !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect();
+    Some((kind, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Ram;
+
+    const PROGRAM: &str = "S10701004E714E7575\nS2060102001234B0\nS9030100FB\n";
+
+    #[test]
+    fn loads_data_and_entry() {
+        let mut ram = Ram::new(0, 0x20000);
+        assert_eq!(load_srecord(&mut ram, PROGRAM), Ok(0x100));
+        assert_eq!(ram.bytes[0x100..0x104], [0x4e, 0x71, 0x4e, 0x75]);
+        assert_eq!(ram.bytes[0x10200..0x10202], [0x12, 0x34]);
+    }
+
+    #[test]
+    fn errors() {
+        let mut ram = Ram::new(0, 0x1000);
+        let bad_sum = PROGRAM.replace("B0", "B1");
+        assert_eq!(
+            load_srecord(&mut ram, &bad_sum),
+            Err(SRecError::Checksum(2))
+        );
+        assert_eq!(
+            load_srecord(&mut ram, "S1zz"),
+            Err(SRecError::Malformed(1))
+        );
+        assert_eq!(
+            load_srecord(&mut ram, PROGRAM),
+            Err(SRecError::Bus(0x10200))
+        );
+        let no_entry = "S10701004E714E7575";
+        assert_eq!(load_srecord(&mut ram, no_entry), Err(SRecError::NoEntry));
+    }
+}
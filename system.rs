@@ -0,0 +1,65 @@
+use crate::bus::{Bus, BusError, FunctionCode, Ram};
+use crate::duart::Duart;
+use crate::Size;
+
+// A minimal board: RAM from address 0, holding the ROM image and its vector
+// table, and a DUART for the console.
+pub struct System {
+    pub ram: Ram,
+    pub duart: Duart,
+}
+
+pub const RAM_SIZE: usize = 0x10_0000;
+pub const DUART_BASE: u32 = 0xff_0000;
+
+impl System {
+    pub fn new(rom: &[u8]) -> System {
+        let mut ram = Ram::new(0, RAM_SIZE.max(rom.len()));
+        ram.bytes[..rom.len()].copy_from_slice(rom);
+        System {
+            ram,
+            duart: Duart::new(DUART_BASE),
+        }
+    }
+
+    fn hits_duart(&self, addr: u32) -> bool {
+        addr.wrapping_sub(DUART_BASE) < 32
+    }
+}
+
+impl Bus for System {
+    fn read(
+        &mut self,
+        addr: u32,
+        s: Size,
+        fc: FunctionCode,
+    ) -> Result<u32, BusError> {
+        if self.hits_duart(addr) {
+            self.duart.read(addr, s, fc)
+        } else {
+            self.ram.read(addr, s, fc)
+        }
+    }
+
+    fn write(
+        &mut self,
+        addr: u32,
+        s: Size,
+        x: u32,
+        fc: FunctionCode,
+    ) -> Result<(), BusError> {
+        if self.hits_duart(addr) {
+            self.duart.write(addr, s, x, fc)
+        } else {
+            self.ram.write(addr, s, x, fc)
+        }
+    }
+
+    fn set_lock(&mut self, locked: bool) {
+        self.ram.set_lock(locked)
+    }
+
+    fn reset_devices(&mut self) {
+        self.duart.reset_devices()
+    }
+}